@@ -0,0 +1,122 @@
+-- Tables and columns written by the ETL on top of the transactions, contracts and blocks
+-- tables, safe to run again on every deploy
+
+ALTER TABLE transactions
+    ADD COLUMN IF NOT EXISTS contract_addresses TEXT[] NOT NULL DEFAULT '{}',
+    ADD COLUMN IF NOT EXISTS status BIGINT,
+    ADD COLUMN IF NOT EXISTS gas_price NUMERIC,
+    ADD COLUMN IF NOT EXISTS nonce NUMERIC,
+    ADD COLUMN IF NOT EXISTS effective_gas_price NUMERIC,
+    ADD COLUMN IF NOT EXISTS fee NUMERIC,
+    ADD COLUMN IF NOT EXISTS l1_block_number BIGINT,
+    ADD COLUMN IF NOT EXISTS l1_gas_used NUMERIC,
+    ADD COLUMN IF NOT EXISTS l1_fee NUMERIC,
+    ADD COLUMN IF NOT EXISTS l1_blob_base_fee NUMERIC,
+    ADD COLUMN IF NOT EXISTS blob_versioned_hashes TEXT[] NOT NULL DEFAULT '{}',
+    ADD COLUMN IF NOT EXISTS point_evaluation_hashes TEXT[] NOT NULL DEFAULT '{}',
+    ADD COLUMN IF NOT EXISTS provisional BOOLEAN NOT NULL DEFAULT false;
+
+ALTER TABLE blocks
+    ALTER COLUMN difficulty TYPE NUMERIC,
+    ALTER COLUMN total_difficulty TYPE NUMERIC,
+    ALTER COLUMN base_fee_per_gas TYPE NUMERIC,
+    ADD COLUMN IF NOT EXISTS l1_block_number BIGINT,
+    ADD COLUMN IF NOT EXISTS blob_gas_used BIGINT,
+    ADD COLUMN IF NOT EXISTS excess_blob_gas BIGINT,
+    ADD COLUMN IF NOT EXISTS parent_beacon_block_root TEXT,
+    ADD COLUMN IF NOT EXISTS uncles_hash TEXT,
+    ADD COLUMN IF NOT EXISTS state_root TEXT,
+    ADD COLUMN IF NOT EXISTS transactions_root TEXT,
+    ADD COLUMN IF NOT EXISTS receipts_root TEXT,
+    ADD COLUMN IF NOT EXISTS logs_bloom TEXT,
+    ADD COLUMN IF NOT EXISTS extra_data TEXT,
+    ADD COLUMN IF NOT EXISTS mix_hash TEXT,
+    ADD COLUMN IF NOT EXISTS withdrawals_root TEXT,
+    ADD COLUMN IF NOT EXISTS withdrawal_count INT;
+
+CREATE TABLE IF NOT EXISTS withdrawals (
+    chain_id BIGINT NOT NULL,
+    index NUMERIC NOT NULL,
+    block_number BIGINT NOT NULL,
+    validator_index NUMERIC NOT NULL,
+    address TEXT NOT NULL,
+    -- In gwei
+    amount NUMERIC NOT NULL,
+    PRIMARY KEY (chain_id, index)
+);
+
+CREATE TABLE IF NOT EXISTS blobs (
+    chain_id BIGINT NOT NULL,
+    versioned_hash TEXT NOT NULL,
+    transaction_hash TEXT NOT NULL,
+    block_number BIGINT NOT NULL,
+    index INT NOT NULL,
+    PRIMARY KEY (chain_id, versioned_hash, transaction_hash)
+);
+
+CREATE TABLE IF NOT EXISTS logs (
+    chain_id BIGINT NOT NULL,
+    transaction_hash TEXT NOT NULL,
+    log_index BIGINT NOT NULL,
+    address TEXT NOT NULL,
+    topics TEXT[] NOT NULL,
+    data TEXT NOT NULL,
+    block_number BIGINT NOT NULL,
+    PRIMARY KEY (chain_id, transaction_hash, log_index)
+);
+
+-- Blocks of each provider chain whose results are persisted, the last ones up to the
+-- chain's `max_reorg_depth`
+CREATE TABLE IF NOT EXISTS checkpoints (
+    chain_id BIGINT NOT NULL,
+    block_number BIGINT NOT NULL,
+    block_hash TEXT NOT NULL,
+    PRIMARY KEY (chain_id, block_number)
+);
+
+ALTER TABLE checkpoints
+    DROP CONSTRAINT checkpoints_pkey,
+    ADD PRIMARY KEY (chain_id, block_number);
+
+-- Kafka offsets of the clusters with `store_offsets`
+CREATE TABLE IF NOT EXISTS kafka_offsets (
+    group_id TEXT NOT NULL,
+    cluster TEXT NOT NULL DEFAULT '',
+    topic TEXT NOT NULL,
+    partition INT NOT NULL,
+    "offset" BIGINT NOT NULL,
+    PRIMARY KEY (group_id, cluster, topic, partition)
+);
+
+ALTER TABLE kafka_offsets
+    ADD COLUMN IF NOT EXISTS cluster TEXT NOT NULL DEFAULT '',
+    DROP CONSTRAINT kafka_offsets_pkey,
+    ADD PRIMARY KEY (group_id, cluster, topic, partition);
+
+-- Start offset last applied to each partition of a consumer group, as JSON
+CREATE TABLE IF NOT EXISTS kafka_start_offsets (
+    group_id TEXT NOT NULL,
+    cluster TEXT NOT NULL DEFAULT '',
+    topic TEXT NOT NULL,
+    partition INT NOT NULL,
+    start TEXT NOT NULL,
+    PRIMARY KEY (group_id, cluster, topic, partition)
+);
+
+-- Chains managed through the admin routes
+CREATE TABLE IF NOT EXISTS chains (
+    id BIGINT PRIMARY KEY,
+    config TEXT NOT NULL,
+    status TEXT NOT NULL
+);
+
+-- Dead-letter table, created under the name set in the chain's `dead_letter` option
+CREATE TABLE IF NOT EXISTS dead_letters (
+    chain_id BIGINT NOT NULL,
+    topic TEXT NOT NULL,
+    partition INT NOT NULL,
+    "offset" BIGINT NOT NULL,
+    error TEXT NOT NULL,
+    -- A message read again after a restart is written again
+    payload BYTEA NOT NULL
+);
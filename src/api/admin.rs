@@ -0,0 +1,100 @@
+use axum::{
+    extract::{Path, Request},
+    http::{header::AUTHORIZATION, StatusCode},
+    middleware::{from_fn, Next},
+    response::Response,
+    routing::{delete, get, post},
+    Json, Router,
+};
+use serde_json::{json, Value};
+use subtle::ConstantTimeEq;
+
+use crate::{
+    config::{Chain, CONFIG},
+    consumer::SUPERVISOR,
+    registry::{ChainStatus, RegistryError, CHAINS},
+};
+
+/// Chain management, every route needs the `ADMIN_TOKEN` as a bearer token
+pub fn routes() -> Router {
+    Router::new()
+        .route("/admin/chains", get(list).post(add))
+        .route("/admin/chains/:id", delete(remove))
+        .route("/admin/chains/:id/pause", post(pause))
+        .route("/admin/chains/:id/resume", post(resume))
+        .route_layer(from_fn(authorize))
+}
+
+async fn authorize(request: Request, next: Next) -> Result<Response, StatusCode> {
+    let Some(token) = CONFIG.admin_token.as_deref() else {
+        return Err(StatusCode::NOT_FOUND);
+    };
+    let bearer = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "));
+    match bearer {
+        // Compared in constant time so the token can't be guessed from response times
+        Some(bearer) if bool::from(bearer.as_bytes().ct_eq(token.as_bytes())) => {
+            Ok(next.run(request).await)
+        }
+        _ => Err(StatusCode::UNAUTHORIZED),
+    }
+}
+
+pub async fn list() -> (StatusCode, Json<Value>) {
+    let chains = CHAINS
+        .list()
+        .into_iter()
+        .map(|(chain, status)| json!({ "chain": chain, "status": status }))
+        .collect::<Vec<_>>();
+    (StatusCode::OK, Json(json!({ "chains": chains })))
+}
+
+pub async fn add(Json(chain): Json<Chain>) -> (StatusCode, Json<Value>) {
+    let chain_id = chain.chain_id();
+    reply(CHAINS.add(chain).await, StatusCode::CREATED, chain_id)
+}
+
+pub async fn pause(Path(chain_id): Path<u64>) -> (StatusCode, Json<Value>) {
+    set_status(chain_id, ChainStatus::Paused).await
+}
+
+pub async fn resume(Path(chain_id): Path<u64>) -> (StatusCode, Json<Value>) {
+    set_status(chain_id, ChainStatus::Running).await
+}
+
+pub async fn remove(Path(chain_id): Path<u64>) -> (StatusCode, Json<Value>) {
+    set_status(chain_id, ChainStatus::Removed).await
+}
+
+async fn set_status(chain_id: u64, status: ChainStatus) -> (StatusCode, Json<Value>) {
+    reply(
+        CHAINS.set_status(chain_id, status).await,
+        StatusCode::OK,
+        chain_id,
+    )
+}
+
+/// Apply a registry change to the running consumers
+fn reply(
+    result: Result<(), RegistryError>,
+    success: StatusCode,
+    chain_id: u64,
+) -> (StatusCode, Json<Value>) {
+    let error = match result {
+        Ok(()) => {
+            SUPERVISOR.sync();
+            return (success, Json(json!({ "chain_id": chain_id })));
+        }
+        Err(e) => e,
+    };
+    let status = match error {
+        RegistryError::NotFound(_) => StatusCode::NOT_FOUND,
+        RegistryError::Exists(_) => StatusCode::CONFLICT,
+        RegistryError::Invalid(_) => StatusCode::BAD_REQUEST,
+        RegistryError::Storage(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    (status, Json(json!({ "error": error.to_string() })))
+}
@@ -1,13 +1,16 @@
 use axum::{http::StatusCode, routing::get, Json, Router};
 use serde_json::{json, Map, Value};
 
+mod admin;
 mod stats;
 pub use stats::STATS;
 
 use self::stats::Stats;
 
 pub fn routes() -> Router {
-    Router::new().route("/health", get(health))
+    Router::new()
+        .route("/health", get(health))
+        .merge(admin::routes())
 }
 
 pub async fn health() -> (StatusCode, Json<Value>) {
@@ -1,9 +1,8 @@
 use std::{collections::HashMap, sync::Arc};
 
-use anyhow::Result;
 use once_cell::sync::Lazy;
 use serde_json::{json, Value};
-use tokio::{spawn, sync::RwLock, task::JoinHandle};
+use tokio::sync::RwLock;
 
 use crate::{channels::CHANNEL, consumer::Commiter, types::EtlResult};
 
@@ -34,46 +33,50 @@ impl Stats {
             .collect()
     }
 
-    pub fn watch(&self) -> JoinHandle<Result<()>> {
-        let stats = self.0.clone();
-        spawn(async move {
-            let mut rx = CHANNEL.result_tx.subscribe();
-            while let Ok((results, tc)) = rx.recv().await {
-                for result in results {
-                    match result {
-                        EtlResult::BlockWithChainId(b) => {
-                            stats
-                                .write()
-                                .await
-                                .entry(("latest_block", Some(b.chain_id.to_string())))
-                                .and_modify(|v| *v = b.block.number)
-                                .or_insert(b.block.number);
-                        }
-                        EtlResult::Transaction(tx) => {
-                            stats
-                                .write()
-                                .await
-                                .entry(("latest_transaction_block", Some(tx.chain_id.to_string())))
-                                .and_modify(|v| *v = tx.block_number)
-                                .or_insert(tx.block_number);
-                        }
-                        _ => {}
-                    };
-                }
+    pub async fn set(&self, key: &'static str, chain_id: Option<u64>, value: u64) {
+        self.0
+            .write()
+            .await
+            .insert((key, chain_id.map(|c| c.to_string())), value);
+    }
 
-                if let Commiter::Kafka(tc) = tc {
-                    stats
-                        .write()
-                        .await
-                        .insert((tc.topic_id, None), tc.offset as u64);
+    /// Increment a counter and return its new value
+    pub async fn increment(&self, key: &'static str, chain_id: Option<u64>) -> u64 {
+        let mut stats = self.0.write().await;
+        let value = stats
+            .entry((key, chain_id.map(|c| c.to_string())))
+            .or_default();
+        *value += 1;
+        *value
+    }
+
+    /// Track the latest block and offset of each chain from the results being dumped
+    pub async fn record(&self, results: &[EtlResult], commiter: &Commiter) {
+        let mut stats = self.0.write().await;
+        for result in results {
+            match result {
+                EtlResult::BlockWithChainId(b) => {
+                    stats.insert(
+                        ("latest_block", Some(b.chain_id.to_string())),
+                        b.block.number,
+                    );
+                }
+                EtlResult::Transaction(tx) if !tx.provisional => {
+                    stats.insert(
+                        ("latest_transaction_block", Some(tx.chain_id.to_string())),
+                        tx.block_number,
+                    );
                 }
-            }
+                _ => {}
+            };
+        }
 
-            Ok(())
-        })
+        if let Commiter::Kafka(tc) = commiter {
+            stats.insert((tc.topic_id, None), tc.offset as u64);
+        }
     }
 
     pub fn queue() -> usize {
-        CHANNEL.result_tx.len()
+        CHANNEL.len()
     }
 }
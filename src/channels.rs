@@ -1,34 +1,99 @@
-use once_cell::sync::Lazy;
-use tokio::{
-    spawn,
-    sync::broadcast::{channel, Sender},
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
 };
 
-use crate::{consumer::Commiter, types::EtlResult};
+use anyhow::{anyhow, Result};
+use log::info;
+use once_cell::sync::Lazy;
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+
+use crate::{api::STATS, consumer::Commiter, types::EtlResult};
 
 pub static CHANNEL: Lazy<Channel> = Lazy::new(Channel::new);
 
+/// Batches of results held before senders wait for the dumper
+const CAPACITY: usize = 10_000;
+/// Results of a chain between two log lines
+const LOG_INTERVAL: u64 = 10_000;
+
+pub type ResultMessage = (Vec<EtlResult>, Commiter);
+
+/// Results of every consumer on their way to the dumper. Sending waits while the channel is
+/// full, so a slow dumper slows the consumers down and no result is dropped.
 pub struct Channel {
-    pub result_tx: Sender<(Vec<EtlResult>, Commiter)>,
+    /// Dropped on close, so the receiver ends once every batch sent before is received
+    result_tx: Mutex<Option<Sender<ResultMessage>>>,
+    result_rx: Mutex<Option<Receiver<ResultMessage>>>,
+    /// Batches sent and not received yet
+    queued: Arc<AtomicUsize>,
 }
 
 impl Channel {
     pub fn new() -> Self {
-        let (result_tx, _) = channel(100_000);
-        Self { result_tx }
+        let (result_tx, result_rx) = channel(CAPACITY);
+        Self {
+            result_tx: Mutex::new(Some(result_tx)),
+            result_rx: Mutex::new(Some(result_rx)),
+            queued: Arc::default(),
+        }
     }
 
-    pub fn send_result(
+    /// Send results in order, offsets are only committed correctly if a result is never
+    /// received before the ones sent earlier. Fails once the channel is closed or the
+    /// receiver is gone.
+    pub async fn send_result(
         &self,
         result: Vec<EtlResult>,
-        topic_commiter: impl Into<Commiter> + Send + 'static,
-    ) {
-        let result_tx = self.result_tx.clone();
-        spawn(async move {
-            result_tx
-                .send((result, topic_commiter.into()))
-                .expect("Failed to send result");
-        });
+        topic_commiter: impl Into<Commiter>,
+    ) -> Result<()> {
+        let result_tx = self
+            .result_tx
+            .lock()
+            .expect("poisoned channel lock")
+            .clone()
+            .ok_or_else(|| anyhow!("Result channel is closed"))?;
+        self.queued.fetch_add(1, Ordering::Relaxed);
+        if result_tx
+            .send((result, topic_commiter.into()))
+            .await
+            .is_err()
+        {
+            self.queued.fetch_sub(1, Ordering::Relaxed);
+            return Err(anyhow!("Result channel is closed"));
+        }
+        Ok(())
+    }
+
+    /// Stop accepting results, the receiver still gets the ones sent before
+    pub fn close(&self) {
+        self.result_tx.lock().expect("poisoned channel lock").take();
+    }
+
+    /// Take the only receiver of the channel
+    pub fn receiver(&self) -> Result<ResultReceiver> {
+        self.result_rx
+            .lock()
+            .expect("poisoned channel lock")
+            .take()
+            .map(|rx| ResultReceiver {
+                rx,
+                queued: self.queued.clone(),
+                received: HashMap::new(),
+            })
+            .ok_or_else(|| anyhow!("Result channel already has a receiver"))
+    }
+
+    /// Batches waiting to be received
+    pub fn len(&self) -> usize {
+        self.queued.load(Ordering::Relaxed)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
     }
 }
 
@@ -37,3 +102,103 @@ impl Default for Channel {
         Self::new()
     }
 }
+
+/// Receiving end of the channel, every batch goes through the stats and the log once
+/// received
+pub struct ResultReceiver {
+    rx: Receiver<ResultMessage>,
+    queued: Arc<AtomicUsize>,
+    /// Results received by chain
+    received: HashMap<u64, u64>,
+}
+
+impl ResultReceiver {
+    /// The next batch, `None` once the channel is closed and drained. Not cancel safe, use
+    /// `recv_unrecorded` in a `select!`.
+    pub async fn recv(&mut self) -> Option<ResultMessage> {
+        let message = self.recv_unrecorded().await?;
+        self.record(&message).await;
+        Some(message)
+    }
+
+    /// The next batch like `recv` but cancel safe, it must then go through `record`
+    pub async fn recv_unrecorded(&mut self) -> Option<ResultMessage> {
+        let message = self.rx.recv().await?;
+        self.queued.fetch_sub(1, Ordering::Relaxed);
+        Some(message)
+    }
+
+    /// Count a received batch in the stats and log it
+    pub async fn record(&mut self, (results, commiter): &ResultMessage) {
+        STATS.record(results, commiter).await;
+        self.log(results);
+    }
+
+    fn log(&mut self, results: &[EtlResult]) {
+        for result in results {
+            let received = self.received.entry(result.chain_id()).or_default();
+            *received += 1;
+            if received.is_multiple_of(LOG_INTERVAL) {
+                info!(
+                    "Received {} result traces from chain {}",
+                    received,
+                    result.chain_id()
+                );
+            }
+
+            #[cfg(feature = "trace-result")]
+            match result {
+                EtlResult::BlockWithChainId(b) => {
+                    info!(
+                        "Received block {} from chain {}",
+                        b.block.number,
+                        result.chain_id()
+                    );
+                }
+                _ => {
+                    info!(
+                        "Received result from chain {}: {}",
+                        result.chain_id(),
+                        result
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn single_receiver_until_dropped() {
+        let channel = Channel::new();
+        let mut rx = channel.receiver().unwrap();
+        assert!(channel.receiver().is_err());
+
+        for _ in 0..3 {
+            channel.send_result(vec![], ()).await.unwrap();
+        }
+        assert_eq!(channel.len(), 3);
+        while !channel.is_empty() {
+            rx.recv().await.unwrap();
+        }
+
+        drop(rx);
+        assert!(channel.send_result(vec![], ()).await.is_err());
+        assert!(channel.is_empty());
+    }
+
+    #[tokio::test]
+    async fn drained_after_close() {
+        let channel = Channel::new();
+        let mut rx = channel.receiver().unwrap();
+        channel.send_result(vec![], ()).await.unwrap();
+        channel.close();
+
+        assert!(channel.send_result(vec![], ()).await.is_err());
+        assert!(rx.recv().await.is_some());
+        assert!(rx.recv().await.is_none());
+    }
+}
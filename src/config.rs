@@ -1,4 +1,4 @@
-use std::env::var;
+use std::{collections::HashMap, env::var};
 
 use deadpool_postgres::{Config as PostgresConfig, ManagerConfig, RecyclingMethod};
 use once_cell::sync::Lazy;
@@ -9,7 +9,9 @@ use serde_tuple::{Deserialize_tuple, Serialize_tuple};
 use structstruck::strike;
 
 mod chain;
+mod kafka;
 pub use chain::*;
+pub use kafka::*;
 
 pub static CONFIG: Lazy<Config> = Lazy::new(Config::new);
 
@@ -22,10 +24,16 @@ strike! {
             pub struct {
                 pub url: String,
                 pub group_id: String,
+                #[serde(default)]
                 pub username: String,
+                #[serde(default)]
                 pub password: String,
+                #[serde(default, skip_serializing_if = "KafkaOptions::is_default")]
+                pub options: KafkaOptions,
             }
         >,
+        /// Additional clusters by name, referenced by the kafka chains' `cluster` option
+        pub kafka_clusters: HashMap<String, Kafka>,
         pub postgres:
             #[derive(Serialize, Deserialize)]
             pub struct {
@@ -35,25 +43,63 @@ strike! {
                 pub db: String,
             }
         ,
+        /// When the dumper flushes its batch, whichever limit is reached first
+        pub dumper:
+            #[derive(Serialize, Deserialize)]
+            pub struct {
+                /// Results in a batch
+                pub max_batch_size: usize,
+                /// Approximate size of the results in a batch, in bytes
+                pub max_batch_bytes: usize,
+                /// Milliseconds since the batch received its first result
+                pub max_latency_ms: u64,
+            }
+        ,
         pub redis: Option<String>,
+        pub schema_registry: Option<String>,
         pub chains: Vec<Chain>,
         pub port: u16,
+        /// Bearer token of the admin API, which is disabled when unset
+        pub admin_token: Option<String>,
+        /// Seconds given to drain and commit everything on SIGTERM before exiting anyway
+        pub shutdown_timeout: u64,
     }
 }
 
 impl Config {
     pub fn new() -> Self {
-        Config {
+        let config = Config {
             kafka: var("KAFKA")
                 .ok()
                 .map(|kafka| from_str(&kafka).expect("KAFKA must be a valid JSON array")),
+            kafka_clusters: var("KAFKA_CLUSTERS")
+                .ok()
+                .map(|clusters| {
+                    from_str(&clusters).expect("KAFKA_CLUSTERS must be a valid JSON object")
+                })
+                .unwrap_or_default(),
             postgres: Postgres {
                 host: var("POSTGRES_HOST").expect("POSTGRES_HOST must be set"),
                 username: var("POSTGRES_USERNAME").expect("POSTGRES_USERNAME must be set"),
                 password: var("POSTGRES_PASSWORD").expect("POSTGRES_PASSWORD must be set"),
                 db: var("POSTGRES_DB").expect("POSTGRES_DB must be set"),
             },
+            dumper: Dumper {
+                max_batch_size: var("DUMPER_MAX_BATCH_SIZE")
+                    .unwrap_or("100000".to_string())
+                    .parse()
+                    .expect("DUMPER_MAX_BATCH_SIZE must be a number"),
+                max_batch_bytes: var("DUMPER_MAX_BATCH_BYTES")
+                    .unwrap_or("67108864".to_string())
+                    .parse()
+                    .expect("DUMPER_MAX_BATCH_BYTES must be a number"),
+                max_latency_ms: var("DUMPER_MAX_LATENCY_MS")
+                    .unwrap_or("1000".to_string())
+                    .parse()
+                    .expect("DUMPER_MAX_LATENCY_MS must be a number of milliseconds"),
+            },
             redis: var("REDIS_URL").ok(),
+            schema_registry: var("SCHEMA_REGISTRY_URL").ok(),
             chains: var("CHAINS")
                 .ok()
                 .map(|chains| from_str(&chains).expect("CHAINS must be a valid JSON array"))
@@ -62,15 +108,43 @@ impl Config {
                 .unwrap_or("8080".to_string())
                 .parse()
                 .expect("PORT must be a number"),
+            admin_token: var("ADMIN_TOKEN").ok(),
+            shutdown_timeout: var("SHUTDOWN_TIMEOUT")
+                .unwrap_or("30".to_string())
+                .parse()
+                .expect("SHUTDOWN_TIMEOUT must be a number of seconds"),
+        };
+
+        for chain in &config.chains {
+            if let Chain::Kafka(chain) = chain {
+                if let Some(cluster) = &chain.options.cluster {
+                    assert!(
+                        config.kafka_clusters.contains_key(cluster),
+                        "Kafka cluster {} of chain {} must be set in KAFKA_CLUSTERS",
+                        cluster,
+                        chain.id
+                    );
+                }
+            }
         }
+        config
     }
 
     pub fn postgres_config(&self) -> PostgresConfig {
         self.into()
     }
 
-    pub fn kafka_config(&self) -> Option<ClientConfig> {
-        self.into()
+    /// The named cluster, or `KAFKA` when no cluster is given
+    pub fn kafka_cluster(&self, cluster: Option<&str>) -> Option<&Kafka> {
+        match cluster {
+            Some(cluster) => self.kafka_clusters.get(cluster),
+            None => self.kafka.as_ref(),
+        }
+    }
+
+    /// Client config of the named cluster, or of `KAFKA` when no cluster is given
+    pub fn kafka_config(&self, cluster: Option<&str>) -> Option<ClientConfig> {
+        self.kafka_cluster(cluster).map(ClientConfig::from)
     }
 }
 
@@ -94,21 +168,3 @@ impl From<&Config> for PostgresConfig {
         }
     }
 }
-
-impl From<&Config> for Option<ClientConfig> {
-    fn from(val: &Config) -> Self {
-        val.kafka.as_ref().map(|kafka| {
-            let mut config = ClientConfig::new();
-            config.set("bootstrap.servers", kafka.url.as_str());
-            config.set("security.protocol", "SASL_PLAINTEXT");
-            config.set("sasl.mechanisms", "SCRAM-SHA-256");
-            config.set("group.id", kafka.group_id.as_str());
-            config.set("sasl.username", kafka.username.as_str());
-            config.set("sasl.password", kafka.password.as_str());
-            config.set("auto.offset.reset", "earliest");
-            config.set("socket.timeout.ms", "20000");
-            config.set("session.timeout.ms", "60000");
-            config
-        })
-    }
-}
@@ -1,19 +1,69 @@
+use std::collections::HashMap;
+
 use serde::{Deserialize, Serialize};
 use serde_tuple::{Deserialize_tuple, Serialize_tuple};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[allow(clippy::large_enum_variant)]
 pub enum Chain {
     Provider(ProviderChainConfig),
     Kafka(KafkaChainConfig),
+    File(FileChainConfig),
 }
 
 #[derive(Debug, Clone, Serialize_tuple, Deserialize_tuple)]
 pub struct ProviderChainConfig {
     pub id: u64,
-    pub rpc_url: String,
+    pub rpc_url: RpcUrls,
     pub ws_url: String,
     pub index_block: bool,
     pub index_tx: bool,
+    #[serde(default, skip_serializing_if = "ProviderChainOptions::is_default")]
+    pub options: ProviderChainOptions,
+}
+
+/// Optional per-chain tuning, given as a trailing JSON object in the provider chain tuple
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProviderChainOptions {
+    /// Max concurrent `debug_traceTransaction` calls when falling back from block tracing
+    pub trace_concurrency: usize,
+    /// What to do when block tracing returns a different number of traces than transactions
+    pub on_trace_mismatch: TraceMismatch,
+    /// Only process a block once it is this many blocks behind the head
+    pub confirmations: u64,
+    /// Only process blocks up to the node's "safe" or "finalized" block, overrides `confirmations`
+    pub finality: Option<BlockFinality>,
+    /// Which L2 fields to extract, inferred from the chain id when unset
+    pub family: Option<ChainFamily>,
+    /// Simulate pending transactions with `debug_traceCall` and push them as provisional
+    /// transactions, replaced by the mined ones once included
+    pub mempool: bool,
+    /// Blocks a restart walks back through the checkpoints to find one still canonical
+    pub max_reorg_depth: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TraceMismatch {
+    Abort,
+    /// Re-trace every transaction of the block individually
+    #[default]
+    Repair,
+}
+
+/// Either a single RPC url or a list of weighted endpoints to balance and fail over between
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum RpcUrls {
+    Single(String),
+    Weighted(Vec<RpcEndpoint>),
+}
+
+#[derive(Debug, Clone, Serialize_tuple, Deserialize_tuple)]
+pub struct RpcEndpoint {
+    pub url: String,
+    pub weight: u32,
 }
 
 #[derive(Debug, Clone, Serialize_tuple, Deserialize_tuple)]
@@ -21,6 +71,103 @@ pub struct KafkaChainConfig {
     pub id: u64,
     pub traces_topic: Option<String>,
     pub blocks_topic: Option<String>,
+    #[serde(default, skip_serializing_if = "KafkaChainOptions::is_default")]
+    pub options: KafkaChainOptions,
+}
+
+/// Optional per-chain Kafka settings, given as a trailing JSON object in the kafka chain tuple
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct KafkaChainOptions {
+    /// Name of the `KAFKA_CLUSTERS` entry to consume from instead of `KAFKA`
+    pub cluster: Option<String>,
+    /// Payload encoding by topic name, topics not listed are JSON
+    pub payload_formats: HashMap<String, PayloadFormat>,
+    /// Where to start each topic instead of the committed group offset. Each partition is
+    /// started there once, a changed entry is applied again.
+    pub start_offsets: HashMap<String, StartOffset>,
+    /// RPC endpoint used to look up the timestamp of `block` start offsets
+    pub rpc_url: Option<String>,
+    /// Where to write messages that can't be decoded, they are only logged when unset
+    pub dead_letter: Option<DeadLetterSink>,
+    /// Undecodable messages tolerated by a consumer run before it stops, unlimited when unset
+    pub max_errors: Option<u64>,
+    /// Chainbase transactions topic, enriches transaction results with their receipt status,
+    /// gas price and nonce
+    pub transactions_topic: Option<String>,
+    /// Chainbase logs topic, the logs of the contracts in transaction results are stored
+    pub logs_topic: Option<String>,
+    /// Blocks behind the newest one for which transactions and logs are kept in memory to be
+    /// matched with the traces, 256 when unset
+    pub join_window: Option<u64>,
+    /// Which L2 fields to extract, inferred from the chain id when unset
+    pub family: Option<ChainFamily>,
+}
+
+/// Chains whose blocks and receipts carry the same rollup specific fields
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChainFamily {
+    #[default]
+    Ethereum,
+    /// Arbitrum One and Nova, `l1BlockNumber` and `gasUsedForL1`
+    Arbitrum,
+    /// OP stack chains, `l1Fee`, `l1GasUsed` and `l1BlobBaseFee`
+    Optimism,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeadLetterSink {
+    /// Kafka topic on the chain's cluster
+    Topic(String),
+    /// Postgres table with `chain_id`, `topic`, `partition`, `offset`, `error` and `payload`
+    /// columns
+    Table(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StartOffset {
+    Earliest,
+    Latest,
+    /// Offset by partition, unlisted partitions keep the committed offset
+    Offsets(HashMap<i32, i64>),
+    /// First message at or after this unix timestamp in milliseconds
+    Timestamp(i64),
+    /// First message at or after the timestamp of this block, looked up through the chain's
+    /// `rpc_url`. Messages are produced after their block, so a few earlier blocks may be
+    /// read again.
+    Block(u64),
+}
+
+impl StartOffset {
+    /// Canonical JSON of the start offset, recorded once it is applied to a partition
+    pub fn revision(&self) -> String {
+        serde_json::to_value(self)
+            .expect("start offsets serialize")
+            .to_string()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PayloadFormat {
+    #[default]
+    Json,
+    /// Avro with Confluent schema registry framing
+    Avro,
+    /// Protobuf with Confluent schema registry framing
+    Protobuf,
+}
+
+/// Exported trace and block files, each path is either a single file or a directory whose
+/// files are processed in name order
+#[derive(Debug, Clone, Serialize_tuple, Deserialize_tuple)]
+pub struct FileChainConfig {
+    pub id: u64,
+    pub traces_path: Option<String>,
+    pub blocks_path: Option<String>,
 }
 
 impl Chain {
@@ -28,10 +175,103 @@ impl Chain {
         match self {
             Chain::Provider(config) => config.id,
             Chain::Kafka(config) => config.id,
+            Chain::File(config) => config.id,
         }
     }
 }
 
+impl RpcUrls {
+    pub fn endpoints(&self) -> Vec<RpcEndpoint> {
+        match self {
+            RpcUrls::Single(url) => vec![RpcEndpoint {
+                url: url.clone(),
+                weight: 1,
+            }],
+            RpcUrls::Weighted(endpoints) => endpoints.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BlockFinality {
+    Safe,
+    Finalized,
+}
+
+impl Default for ProviderChainOptions {
+    fn default() -> Self {
+        Self {
+            trace_concurrency: 8,
+            on_trace_mismatch: TraceMismatch::default(),
+            confirmations: 0,
+            finality: None,
+            family: None,
+            mempool: false,
+            max_reorg_depth: 64,
+        }
+    }
+}
+
+impl ProviderChainOptions {
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Whether blocks are processed as soon as they are announced
+    pub fn follows_tip(&self) -> bool {
+        self.confirmations == 0 && self.finality.is_none()
+    }
+}
+
+impl KafkaChainOptions {
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    pub fn payload_format(&self, topic: &str) -> PayloadFormat {
+        self.payload_formats.get(topic).copied().unwrap_or_default()
+    }
+
+    /// Whether transaction results are joined with a transactions or logs topic
+    pub fn joins_transactions(&self) -> bool {
+        self.transactions_topic.is_some() || self.logs_topic.is_some()
+    }
+}
+
+impl ProviderChainConfig {
+    pub fn family(&self) -> ChainFamily {
+        self.options
+            .family
+            .unwrap_or_else(|| ChainFamily::from_chain_id(self.id))
+    }
+}
+
+impl KafkaChainConfig {
+    pub fn family(&self) -> ChainFamily {
+        self.options
+            .family
+            .unwrap_or_else(|| ChainFamily::from_chain_id(self.id))
+    }
+}
+
+impl ChainFamily {
+    /// Family of the well known mainnets and testnets, anything else is treated as Ethereum
+    pub fn from_chain_id(chain_id: u64) -> Self {
+        match chain_id {
+            42161 | 42170 | 421614 => Self::Arbitrum,
+            10 | 8453 | 11155420 | 84532 => Self::Optimism,
+            _ => Self::Ethereum,
+        }
+    }
+}
+
+impl From<&str> for RpcUrls {
+    fn from(url: &str) -> Self {
+        RpcUrls::Single(url.to_string())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -41,15 +281,17 @@ mod tests {
         let config = vec![
             Chain::Provider(ProviderChainConfig {
                 id: 1,
-                rpc_url: "http://localhost:8545".to_string(),
+                rpc_url: "http://localhost:8545".into(),
                 ws_url: "ws://localhost:8546".to_string(),
                 index_block: true,
                 index_tx: true,
+                options: Default::default(),
             }),
             Chain::Kafka(KafkaChainConfig {
                 id: 2,
                 blocks_topic: Some("blocks".to_string()),
                 traces_topic: Some("traces".to_string()),
+                options: Default::default(),
             }),
         ];
 
@@ -58,4 +300,125 @@ mod tests {
             r#"[{"Provider":[1,"http://localhost:8545","ws://localhost:8546",true,true]},{"Kafka":[2,"traces","blocks"]}]"#
         );
     }
+
+    #[test]
+    fn correct_weighted_rpc_deserialization() {
+        let config: Vec<Chain> = serde_json::from_str(
+            r#"[{"Provider":[1,[["http://a:8545",3],["http://b:8545",1]],"ws://a:8546",true,false]}]"#,
+        )
+        .expect("deserialization failed");
+
+        let Chain::Provider(chain) = &config[0] else {
+            panic!("expected provider chain");
+        };
+        let endpoints = chain.rpc_url.endpoints();
+        assert_eq!(endpoints.len(), 2);
+        assert_eq!(endpoints[0].url, "http://a:8545");
+        assert_eq!(endpoints[0].weight, 3);
+        assert_eq!(endpoints[1].weight, 1);
+        assert!(chain.options.is_default());
+    }
+
+    #[test]
+    fn correct_chain_options_deserialization() {
+        let config: Vec<Chain> = serde_json::from_str(
+            r#"[{"Provider":[1,"http://a:8545","ws://a:8546",true,true,{"on_trace_mismatch":"abort","finality":"safe"}]}]"#,
+        )
+        .expect("deserialization failed");
+
+        let Chain::Provider(chain) = &config[0] else {
+            panic!("expected provider chain");
+        };
+        assert_eq!(chain.options.on_trace_mismatch, TraceMismatch::Abort);
+        assert_eq!(chain.options.trace_concurrency, 8);
+        assert_eq!(chain.options.finality, Some(BlockFinality::Safe));
+        assert!(!chain.options.follows_tip());
+        assert_eq!(chain.family(), ChainFamily::Ethereum);
+
+        let config: Vec<Chain> = serde_json::from_str(
+            r#"[{"Provider":[42161,"http://a:8545","ws://a:8546",true,true]},{"Provider":[1301,"http://b:8545","ws://b:8546",true,true,{"family":"optimism"}]}]"#,
+        )
+        .expect("deserialization failed");
+        let families = config
+            .iter()
+            .map(|c| match c {
+                Chain::Provider(chain) => chain.family(),
+                _ => panic!("expected provider chain"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(families, vec![ChainFamily::Arbitrum, ChainFamily::Optimism]);
+    }
+
+    #[test]
+    fn correct_kafka_payload_format_deserialization() {
+        let config: Vec<Chain> = serde_json::from_str(
+            r#"[{"Kafka":[2,"traces","blocks",{"payload_formats":{"traces":"avro"}}]}]"#,
+        )
+        .expect("deserialization failed");
+
+        let Chain::Kafka(chain) = &config[0] else {
+            panic!("expected kafka chain");
+        };
+        assert_eq!(chain.options.payload_format("traces"), PayloadFormat::Avro);
+        assert_eq!(chain.options.payload_format("blocks"), PayloadFormat::Json);
+    }
+
+    #[test]
+    fn correct_kafka_dead_letter_deserialization() {
+        let config: Vec<Chain> = serde_json::from_str(
+            r#"[{"Kafka":[2,"traces",null,{"dead_letter":{"topic":"traces-dlq"},"max_errors":100}]}]"#,
+        )
+        .expect("deserialization failed");
+
+        let Chain::Kafka(chain) = &config[0] else {
+            panic!("expected kafka chain");
+        };
+        assert_eq!(
+            chain.options.dead_letter,
+            Some(DeadLetterSink::Topic("traces-dlq".to_string()))
+        );
+        assert_eq!(chain.options.max_errors, Some(100));
+    }
+
+    #[test]
+    fn correct_kafka_start_offsets_deserialization() {
+        let config: Vec<Chain> = serde_json::from_str(
+            r#"[{"Kafka":[2,"traces","blocks",{"start_offsets":{"traces":{"offsets":{"0":1200,"3":8}},"blocks":"latest","logs":{"block":19000000}},"rpc_url":"http://a:8545"}]}]"#,
+        )
+        .expect("deserialization failed");
+
+        let Chain::Kafka(chain) = &config[0] else {
+            panic!("expected kafka chain");
+        };
+        assert_eq!(
+            chain.options.start_offsets.get("traces"),
+            Some(&StartOffset::Offsets(HashMap::from([(0, 1200), (3, 8)])))
+        );
+        assert_eq!(
+            chain.options.start_offsets.get("blocks"),
+            Some(&StartOffset::Latest)
+        );
+        assert_eq!(
+            chain.options.start_offsets.get("logs"),
+            Some(&StartOffset::Block(19000000))
+        );
+    }
+
+    #[test]
+    fn correct_kafka_transaction_topics_deserialization() {
+        let config: Vec<Chain> = serde_json::from_str(
+            r#"[{"Kafka":[2,"traces","blocks",{"transactions_topic":"transactions","logs_topic":"logs"}]}]"#,
+        )
+        .expect("deserialization failed");
+
+        let Chain::Kafka(chain) = &config[0] else {
+            panic!("expected kafka chain");
+        };
+        assert_eq!(
+            chain.options.transactions_topic.as_deref(),
+            Some("transactions")
+        );
+        assert_eq!(chain.options.logs_topic.as_deref(), Some("logs"));
+        assert!(chain.options.joins_transactions());
+    }
 }
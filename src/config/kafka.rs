@@ -0,0 +1,166 @@
+use std::collections::HashMap;
+
+use rdkafka::ClientConfig;
+use serde::{Deserialize, Serialize};
+
+use super::Kafka;
+
+/// Optional Kafka client settings, given as a trailing JSON object in the kafka tuple
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct KafkaOptions {
+    pub security_protocol: SecurityProtocol,
+    /// SASL mechanism as named by librdkafka, e.g. `SCRAM-SHA-512` or `PLAIN`
+    pub sasl_mechanism: String,
+    pub ssl_ca_location: Option<String>,
+    /// Client certificate for mTLS
+    pub ssl_certificate_location: Option<String>,
+    pub ssl_key_location: Option<String>,
+    pub ssl_key_password: Option<String>,
+    pub auto_offset_reset: OffsetReset,
+    /// Store consumed offsets in Postgres along with the results and resume from them,
+    /// broker commits are then only advisory
+    pub store_offsets: bool,
+    /// Raw librdkafka properties, applied last so they override everything above
+    pub properties: HashMap<String, String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SecurityProtocol {
+    Plaintext,
+    Ssl,
+    #[default]
+    SaslPlaintext,
+    SaslSsl,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OffsetReset {
+    #[default]
+    Earliest,
+    Latest,
+    /// Fail instead of guessing when the group has no committed offset
+    Error,
+}
+
+impl Default for KafkaOptions {
+    fn default() -> Self {
+        Self {
+            security_protocol: SecurityProtocol::default(),
+            sasl_mechanism: "SCRAM-SHA-256".to_string(),
+            ssl_ca_location: None,
+            ssl_certificate_location: None,
+            ssl_key_location: None,
+            ssl_key_password: None,
+            auto_offset_reset: OffsetReset::default(),
+            store_offsets: false,
+            properties: HashMap::new(),
+        }
+    }
+}
+
+impl KafkaOptions {
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl SecurityProtocol {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Plaintext => "plaintext",
+            Self::Ssl => "ssl",
+            Self::SaslPlaintext => "sasl_plaintext",
+            Self::SaslSsl => "sasl_ssl",
+        }
+    }
+
+    pub fn is_sasl(&self) -> bool {
+        matches!(self, Self::SaslPlaintext | Self::SaslSsl)
+    }
+}
+
+impl OffsetReset {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Earliest => "earliest",
+            Self::Latest => "latest",
+            Self::Error => "error",
+        }
+    }
+}
+
+impl From<&Kafka> for ClientConfig {
+    fn from(kafka: &Kafka) -> Self {
+        let options = &kafka.options;
+        let mut config = ClientConfig::new();
+        config.set("bootstrap.servers", kafka.url.as_str());
+        config.set("group.id", kafka.group_id.as_str());
+        config.set("security.protocol", options.security_protocol.as_str());
+        if options.security_protocol.is_sasl() {
+            config.set("sasl.mechanisms", options.sasl_mechanism.as_str());
+            config.set("sasl.username", kafka.username.as_str());
+            config.set("sasl.password", kafka.password.as_str());
+        }
+        for (key, value) in [
+            ("ssl.ca.location", &options.ssl_ca_location),
+            (
+                "ssl.certificate.location",
+                &options.ssl_certificate_location,
+            ),
+            ("ssl.key.location", &options.ssl_key_location),
+            ("ssl.key.password", &options.ssl_key_password),
+        ] {
+            if let Some(value) = value {
+                config.set(key, value.as_str());
+            }
+        }
+        config.set("auto.offset.reset", options.auto_offset_reset.as_str());
+        // Offsets are committed once their results are persisted
+        config.set("enable.auto.commit", "false");
+        config.set("socket.timeout.ms", "20000");
+        config.set("session.timeout.ms", "60000");
+        for (key, value) in &options.properties {
+            config.set(key.as_str(), value.as_str());
+        }
+        config
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::from_str;
+
+    use super::*;
+
+    #[test]
+    fn correct_kafka_client_config() {
+        let kafka: Kafka = from_str(r#"["broker:9092","etl","user","pass"]"#).unwrap();
+        let config = ClientConfig::from(&kafka);
+        assert_eq!(config.get("security.protocol"), Some("sasl_plaintext"));
+        assert_eq!(config.get("sasl.mechanisms"), Some("SCRAM-SHA-256"));
+        assert_eq!(config.get("auto.offset.reset"), Some("earliest"));
+        assert_eq!(config.get("enable.auto.commit"), Some("false"));
+
+        let kafka: Kafka = from_str(
+            r#"["broker:9094","etl","","",{
+                "security_protocol":"ssl",
+                "ssl_ca_location":"/certs/ca.pem",
+                "ssl_certificate_location":"/certs/client.pem",
+                "ssl_key_location":"/certs/client.key",
+                "auto_offset_reset":"latest",
+                "properties":{"session.timeout.ms":"30000","fetch.max.bytes":"1048576"}
+            }]"#,
+        )
+        .unwrap();
+        let config = ClientConfig::from(&kafka);
+        assert_eq!(config.get("security.protocol"), Some("ssl"));
+        assert_eq!(config.get("sasl.username"), None);
+        assert_eq!(config.get("ssl.key.location"), Some("/certs/client.key"));
+        assert_eq!(config.get("auto.offset.reset"), Some("latest"));
+        assert_eq!(config.get("session.timeout.ms"), Some("30000"));
+        assert_eq!(config.get("fetch.max.bytes"), Some("1048576"));
+    }
+}
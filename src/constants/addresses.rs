@@ -27,6 +27,14 @@ pub const EC_PAIRING_ADDRESS: Address = H160([
     0x00, 0x08, // 24 bytes
 ]);
 
+/// EIP-4844 KZG point evaluation
+pub const POINT_EVALUATION_ADDRESS: Address = H160([
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // 6 bytes
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // 12 bytes
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // 18 bytes
+    0x00, 0x0a, // 24 bytes
+]);
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -41,5 +49,9 @@ mod tests {
             format!("{:?}", EC_PAIRING_ADDRESS),
             "0x0000000000000000000000000000000000000008"
         );
+        assert_eq!(
+            format!("{:?}", POINT_EVALUATION_ADDRESS),
+            "0x000000000000000000000000000000000000000a"
+        );
     }
 }
@@ -0,0 +1,184 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use anyhow::{anyhow, Result};
+use log::{info, warn};
+use tokio::{
+    runtime::Handle,
+    select,
+    sync::mpsc::{channel, Receiver},
+    task::spawn_blocking,
+};
+
+mod parquet;
+mod reader;
+pub use parquet::*;
+pub use reader::*;
+
+use crate::{
+    api::STATS,
+    channels::CHANNEL,
+    config::FileChainConfig,
+    shutdown::SHUTDOWN,
+    types::{Block, BlockWithChainId, EtlResult, Trace, TraceTree},
+};
+
+/// Records between two progress reports
+const PROGRESS_INTERVAL: u64 = 100_000;
+const BLOCK_BATCH_SIZE: usize = 1_000;
+/// Interval at which a finished ingestion checks whether its results were received
+const DRAIN_POLL: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone)]
+pub struct FileConsumer;
+
+impl FileConsumer {
+    pub async fn ingest(chain: &'static FileChainConfig) -> Result<()> {
+        if let Some(path) = &chain.blocks_path {
+            info!(
+                "Starting file block consumer for {} from {}",
+                chain.id, path
+            );
+            let mut rx = Self::read::<Block>(chain.id, path)?;
+            let mut blocks = vec![];
+            while let Some(block) = Self::next(&mut rx).await {
+                blocks.push(
+                    BlockWithChainId {
+                        chain_id: chain.id,
+                        block: block?,
+                    }
+                    .into(),
+                );
+                if blocks.len() >= BLOCK_BATCH_SIZE {
+                    Self::send(std::mem::take(&mut blocks)).await?;
+                }
+            }
+            Self::send(blocks).await?;
+        }
+
+        if let Some(path) = &chain.traces_path {
+            info!(
+                "Starting file trace consumer for {} from {}",
+                chain.id, path
+            );
+            let mut rx = Self::read::<Trace>(chain.id, path)?;
+            let mut trace_tree = TraceTree::new(chain.id);
+            while let Some(trace) = Self::next(&mut rx).await {
+                let trace = trace?;
+                if trace.trace_address.is_empty() {
+                    if let Some(results) = trace_tree.commit() {
+                        Self::send(results).await?;
+                    }
+
+                    trace_tree.reset(&trace);
+                }
+
+                trace_tree.add_trace(trace);
+            }
+            // Unlike a stream, the last transaction of a file is never followed by a new root,
+            // nor is the one being read on shutdown
+            if let Some(results) = trace_tree.commit() {
+                Self::send(results).await?;
+            }
+        }
+
+        if SHUTDOWN.is_triggered() {
+            return Ok(());
+        }
+        info!("Finished file ingestion for chain {}", chain.id);
+        // Finished once the dumper received what was read
+        while !CHANNEL.is_empty() && SHUTDOWN.sleep(DRAIN_POLL).await {}
+        Ok(())
+    }
+
+    /// Next record read, `None` once every file is read or on shutdown
+    async fn next<T>(rx: &mut Receiver<Result<T>>) -> Option<Result<T>> {
+        select! {
+            record = rx.recv() => record,
+            _ = SHUTDOWN.wait() => None,
+        }
+    }
+
+    /// Send results once the channel has room, files are read far faster than the
+    /// results can be dumped
+    async fn send(results: Vec<EtlResult>) -> Result<()> {
+        if results.is_empty() {
+            return Ok(());
+        }
+        CHANNEL.send_result(results, ()).await
+    }
+
+    /// Read every record under `path` on a blocking thread, in file order. Records that
+    /// cannot be decoded are counted and skipped, any other error ends the reading.
+    fn read<T: FileRecord>(chain_id: u64, path: &str) -> Result<Receiver<Result<T>>> {
+        let sources = FileSource::list(path)?;
+        let (tx, rx) = channel(10_000);
+        let handle = Handle::current();
+        spawn_blocking(move || {
+            let mut records = 0u64;
+            for (i, source) in sources.iter().enumerate() {
+                let size = source.path.metadata().map(|m| m.len()).unwrap_or_default();
+                let read = Arc::new(AtomicU64::new(0));
+                info!(
+                    "Reading file {}/{} {} for chain {}",
+                    i + 1,
+                    sources.len(),
+                    source.path.display(),
+                    chain_id
+                );
+
+                let iter = match source.records::<T>(read.clone()) {
+                    Ok(iter) => iter,
+                    Err(e) => {
+                        tx.blocking_send(Err(e)).ok();
+                        return;
+                    }
+                };
+                for record in iter {
+                    let record = match record {
+                        Ok(record) => record,
+                        Err(e) if e.is::<BadRecord>() => {
+                            warn!(
+                                "Skipping invalid record in {}: {}",
+                                source.path.display(),
+                                e
+                            );
+                            handle.block_on(STATS.increment("file_bad_records", Some(chain_id)));
+                            continue;
+                        }
+                        Err(e) => {
+                            let e = anyhow!("Failed to read {}: {}", source.path.display(), e);
+                            tx.blocking_send(Err(e)).ok();
+                            return;
+                        }
+                    };
+                    if tx.blocking_send(Ok(record)).is_err() {
+                        return;
+                    }
+
+                    records += 1;
+                    if records.is_multiple_of(PROGRESS_INTERVAL) {
+                        info!(
+                            "Read {} records for chain {}, file {}/{} at {}%",
+                            records,
+                            chain_id,
+                            i + 1,
+                            sources.len(),
+                            read.load(Ordering::Relaxed) * 100 / size.max(1)
+                        );
+                        handle.block_on(STATS.set("file_records", Some(chain_id), records));
+                    }
+                }
+                handle.block_on(STATS.set("files_done", Some(chain_id), (i + 1) as u64));
+            }
+            info!("Read {} records for chain {} in total", records, chain_id);
+            handle.block_on(STATS.set("file_records", Some(chain_id), records));
+        });
+        Ok(rx)
+    }
+}
@@ -0,0 +1,224 @@
+use std::{
+    fs::File,
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use anyhow::{bail, Result};
+use ethers::types::U256;
+use parquet::{
+    file::reader::{FileReader, SerializedFileReader},
+    record::Field,
+};
+use serde_json::{from_value, Map, Number, Value};
+
+use crate::types::{Block, Log, Trace, TransactionDetails};
+
+use super::{text_value, BadRecord, FileRecord};
+
+/// Columns holding 256 bits integers, written as big endian binary by cryo
+const U256_COLUMNS: &[&str] = &[
+    "value",
+    "difficulty",
+    "total_difficulty",
+    "base_fee_per_gas",
+];
+
+impl FileRecord for Trace {
+    const COLUMN_ALIASES: &'static [(&'static str, &'static str)] = &[
+        ("action_from", "from_address"),
+        ("action_to", "to_address"),
+        ("action_value", "value"),
+        ("action_value_string", "value"),
+        ("action_value_binary", "value"),
+        ("action_gas", "gas"),
+        ("action_input", "input"),
+        ("action_call_type", "call_type"),
+        ("action_reward_type", "reward_type"),
+        ("action_type", "trace_type"),
+        ("result_gas_used", "gas_used"),
+        ("result_output", "output"),
+        ("transaction_position", "transaction_index"),
+    ];
+
+    fn missing_columns() -> Vec<(&'static str, Value)> {
+        vec![("value", Value::Null)]
+    }
+}
+
+impl FileRecord for Block {
+    const COLUMN_ALIASES: &'static [(&'static str, &'static str)] = &[
+        ("block_number", "number"),
+        ("block_hash", "hash"),
+        ("author", "miner"),
+        ("total_difficulty_string", "total_difficulty"),
+        ("total_difficulty_binary", "total_difficulty"),
+        ("base_fee_per_gas_string", "base_fee_per_gas"),
+        ("base_fee_per_gas_binary", "base_fee_per_gas"),
+    ];
+}
+
+impl FileRecord for TransactionDetails {
+    const COLUMN_ALIASES: &'static [(&'static str, &'static str)] =
+        &[("transaction_hash", "hash"), ("receipt_status", "status")];
+}
+
+impl FileRecord for Log {}
+
+/// Iterate over the rows of a Parquet file, decoding one row group at a time. `read` is
+/// advanced in proportion to the rows read since compressed row sizes are unknown.
+pub fn parquet_records<T: FileRecord>(
+    path: &Path,
+    read: Arc<AtomicU64>,
+) -> Result<Box<dyn Iterator<Item = Result<T>> + Send>> {
+    let size = path.metadata()?.len();
+    let reader = SerializedFileReader::new(File::open(path)?)?;
+    let rows = reader.metadata().file_metadata().num_rows().max(1) as u64;
+
+    let mut seen = 0u64;
+    Ok(Box::new(reader.into_iter().map(move |row| {
+        seen += 1;
+        read.store(seen * size / rows, Ordering::Relaxed);
+
+        let row = row?;
+        let mut fields = T::missing_columns()
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v))
+            .collect::<Map<_, _>>();
+        for (name, field) in row.get_column_iter() {
+            let name = T::COLUMN_ALIASES
+                .iter()
+                .find(|(from, _)| from == name)
+                .map_or(name.as_str(), |(_, to)| to);
+            fields.insert(
+                name.to_string(),
+                field_value(name, field).map_err(BadRecord::wrap)?,
+            );
+        }
+        from_value::<T>(Value::Object(fields)).map_err(BadRecord::wrap)
+    })))
+}
+
+fn field_value(column: &str, field: &Field) -> Result<Value> {
+    Ok(match field {
+        Field::Null if column == "trace_address" => Value::Array(vec![]),
+        Field::Null => Value::Null,
+        Field::Bool(b) => Value::Bool(*b),
+        Field::Byte(i) => (*i).into(),
+        Field::Short(i) => (*i).into(),
+        Field::Int(i) => (*i).into(),
+        Field::Long(i) => (*i).into(),
+        Field::UByte(i) => (*i).into(),
+        Field::UShort(i) => (*i).into(),
+        Field::UInt(i) => (*i).into(),
+        Field::ULong(i) => (*i).into(),
+        Field::Float(f) => Number::from_f64(*f as f64).map_or(Value::Null, Value::Number),
+        Field::Double(f) => Number::from_f64(*f).map_or(Value::Null, Value::Number),
+        Field::Str(s) => text_value(column, s)?,
+        Field::Bytes(b) if U256_COLUMNS.contains(&column) => {
+            text_value(column, &U256::from_big_endian(b.data()).to_string())?
+        }
+        Field::Bytes(b) => Value::String(format!("0x{}", hex(b.data()))),
+        Field::ListInternal(list) => Value::Array(
+            list.elements()
+                .iter()
+                .map(|e| field_value(column, e))
+                .collect::<Result<_>>()?,
+        ),
+        _ => bail!("Unsupported parquet value in column {}: {}", column, field),
+    })
+}
+
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use parquet::{
+        data_type::{ByteArray, ByteArrayType, Int64Type},
+        file::{properties::WriterProperties, writer::SerializedFileWriter},
+        schema::parser::parse_message_type,
+    };
+
+    use super::*;
+
+    #[test]
+    fn read_cryo_traces() {
+        let path = std::env::temp_dir().join("zkscan_etl_read_cryo_traces.parquet");
+        let schema = Arc::new(
+            parse_message_type(
+                "message traces {
+                    REQUIRED BINARY action_from;
+                    REQUIRED BINARY action_to;
+                    REQUIRED BINARY action_value_binary;
+                    REQUIRED BINARY action_input;
+                    REQUIRED BINARY trace_address (UTF8);
+                    REQUIRED INT64 subtraces;
+                    REQUIRED INT64 block_number;
+                    REQUIRED INT64 transaction_position;
+                }",
+            )
+            .unwrap(),
+        );
+        let mut writer = SerializedFileWriter::new(
+            File::create(&path).unwrap(),
+            schema,
+            Arc::new(WriterProperties::builder().build()),
+        )
+        .unwrap();
+        let mut row_group = writer.next_row_group().unwrap();
+        let binary_columns: [&[u8]; 5] = [
+            &[0xaa; 20],
+            &[0x08; 20],
+            &[0x01, 0x00],
+            &[0x12, 0x34],
+            b"0_1",
+        ];
+        for data in binary_columns {
+            let mut column = row_group.next_column().unwrap().unwrap();
+            column
+                .typed::<ByteArrayType>()
+                .write_batch(&[ByteArray::from(data.to_vec())], None, None)
+                .unwrap();
+            column.close().unwrap();
+        }
+        for data in [0i64, 100, 7] {
+            let mut column = row_group.next_column().unwrap().unwrap();
+            column
+                .typed::<Int64Type>()
+                .write_batch(&[data], None, None)
+                .unwrap();
+            column.close().unwrap();
+        }
+        row_group.close().unwrap();
+        writer.close().unwrap();
+
+        let read = Arc::new(AtomicU64::new(0));
+        let traces = parquet_records::<Trace>(&path, read.clone())
+            .unwrap()
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        std::fs::remove_file(&path).ok();
+
+        assert_eq!(traces.len(), 1);
+        assert_eq!(traces[0].block_number, 100);
+        assert_eq!(traces[0].transaction_index, Some(7));
+        assert_eq!(traces[0].trace_address, vec![0, 1]);
+        assert_eq!(traces[0].value, Some(U256::from(256)));
+        assert_eq!(
+            traces[0].input.as_ref().map(|i| i.to_vec()),
+            Some(vec![0x12, 0x34])
+        );
+        assert_eq!(
+            format!("{:?}", traces[0].to_address.unwrap()),
+            "0x0808080808080808080808080808080808080808"
+        );
+        assert!(read.load(Ordering::Relaxed) > 0);
+    }
+}
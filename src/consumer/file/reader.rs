@@ -0,0 +1,290 @@
+use std::{
+    fmt::Display,
+    fs::{read_dir, File},
+    io::{BufRead, BufReader, Read},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use anyhow::{anyhow, bail, Result};
+use flate2::read::MultiGzDecoder;
+use serde::de::DeserializeOwned;
+use serde_json::{from_str, from_value, Map, Number, Value};
+
+use super::parquet_records;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileFormat {
+    JsonLines,
+    Csv,
+    Parquet,
+}
+
+/// A record type that can be read from export files
+pub trait FileRecord: DeserializeOwned + Send + 'static {
+    /// Column renames from columnar datasets (e.g. cryo) to the serde field names
+    const COLUMN_ALIASES: &'static [(&'static str, &'static str)] = &[];
+
+    /// Values for required fields that columnar datasets may not have
+    fn missing_columns() -> Vec<(&'static str, Value)> {
+        vec![]
+    }
+}
+
+/// A record that could not be decoded, it is skipped and the rest of its file still read
+#[derive(Debug)]
+pub struct BadRecord(String);
+
+impl BadRecord {
+    pub fn wrap(error: impl Display) -> anyhow::Error {
+        Self(error.to_string()).into()
+    }
+}
+
+impl Display for BadRecord {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for BadRecord {}
+
+/// A single export file, its format is inferred from the extension (`.json`, `.jsonl`,
+/// `.ndjson`, `.csv` or `.parquet`, the text formats optionally followed by `.gz`)
+#[derive(Debug, Clone)]
+pub struct FileSource {
+    pub path: PathBuf,
+    pub format: FileFormat,
+    pub gzip: bool,
+}
+
+impl FileSource {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_str()?.to_lowercase();
+        let (name, gzip) = match name.strip_suffix(".gz") {
+            Some(name) => (name.to_string(), true),
+            None => (name, false),
+        };
+        let format = match Path::new(&name).extension()?.to_str()? {
+            "json" | "jsonl" | "ndjson" => FileFormat::JsonLines,
+            "csv" => FileFormat::Csv,
+            "parquet" if !gzip => FileFormat::Parquet,
+            _ => return None,
+        };
+        Some(Self {
+            path: path.to_path_buf(),
+            format,
+            gzip,
+        })
+    }
+
+    /// All supported files at `path`, a directory is listed in file name order
+    pub fn list(path: impl AsRef<Path>) -> Result<Vec<Self>> {
+        let path = path.as_ref();
+        if !path.is_dir() {
+            return Self::from_path(path)
+                .map(|f| vec![f])
+                .ok_or_else(|| anyhow!("Unsupported file format: {}", path.display()));
+        }
+
+        let mut paths = read_dir(path)?
+            .map(|e| Ok(e?.path()))
+            .collect::<Result<Vec<_>>>()?;
+        paths.sort();
+        Ok(paths.iter().filter_map(|p| Self::from_path(p)).collect())
+    }
+
+    /// Iterate over the records of the file, `read` is updated with the number of bytes
+    /// read from disk so far. Records that cannot be decoded are `BadRecord` errors, any
+    /// other error means the file cannot be read further.
+    pub fn records<T: FileRecord>(
+        &self,
+        read: Arc<AtomicU64>,
+    ) -> Result<Box<dyn Iterator<Item = Result<T>> + Send>> {
+        Ok(match self.format {
+            FileFormat::Parquet => parquet_records(&self.path, read)?,
+            FileFormat::JsonLines => Box::new(
+                self.open(read)?
+                    .lines()
+                    .filter(|l| l.as_ref().map_or(true, |l| !l.trim().is_empty()))
+                    .map(|l| from_str::<T>(&l?).map_err(BadRecord::wrap)),
+            ),
+            FileFormat::Csv => {
+                let mut csv = csv::Reader::from_reader(self.open(read)?);
+                let headers = csv.headers()?.clone();
+                Box::new(csv.into_records().map(move |r| {
+                    let record = r.map_err(|e| match e.is_io_error() {
+                        true => e.into(),
+                        false => BadRecord::wrap(e),
+                    })?;
+                    let row = headers
+                        .iter()
+                        .zip(record.iter())
+                        .map(|(k, v)| Ok((k.to_string(), text_value(k, v)?)))
+                        .collect::<Result<Map<_, _>>>()
+                        .map_err(BadRecord::wrap)?;
+                    from_value::<T>(Value::Object(row)).map_err(BadRecord::wrap)
+                }))
+            }
+        })
+    }
+
+    /// Open a text file, decompressing it when gzipped
+    fn open(&self, read: Arc<AtomicU64>) -> Result<BufReader<Box<dyn Read + Send>>> {
+        let file = CountingReader {
+            inner: File::open(&self.path)?,
+            read,
+        };
+        let reader: Box<dyn Read + Send> = match self.gzip {
+            true => Box::new(MultiGzDecoder::new(file)),
+            false => Box::new(file),
+        };
+        Ok(BufReader::new(reader))
+    }
+}
+
+/// Map a textual cell onto the JSON value the serde types expect: empty cells are null,
+/// `trace_address` is a comma or underscore separated list and decimal cells are numbers
+pub fn text_value(column: &str, cell: &str) -> Result<Value> {
+    let cell = cell.trim();
+    if cell.is_empty() {
+        return Ok(match column {
+            "trace_address" => Value::Array(vec![]),
+            _ => Value::Null,
+        });
+    }
+    if column == "trace_address" {
+        return cell
+            .trim_matches(|c| c == '[' || c == ']')
+            .split([',', '_'])
+            .map(|i| Ok(Value::Number(i.trim().parse::<u32>()?.into())))
+            .collect();
+    }
+    if is_decimal(cell) {
+        return match Number::from_str(cell) {
+            Ok(n) => Ok(Value::Number(n)),
+            Err(e) => bail!("Invalid number {} in column {}: {}", cell, column, e),
+        };
+    }
+    Ok(Value::String(cell.to_string()))
+}
+
+/// Whether a cell is a plain decimal number like `12` or `0.5`
+fn is_decimal(cell: &str) -> bool {
+    let digits = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    match cell.split_once('.') {
+        Some((integer, fraction)) => digits(integer) && digits(fraction),
+        None => digits(cell),
+    }
+}
+
+struct CountingReader<R> {
+    inner: R,
+    read: Arc<AtomicU64>,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.read.fetch_add(n as u64, Ordering::Relaxed);
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::{write::GzEncoder, Compression};
+
+    use super::*;
+    use crate::types::Trace;
+
+    #[test]
+    fn detect_file_format() {
+        let source =
+            FileSource::from_path(Path::new("/data/traces_000.csv.gz")).expect("supported format");
+        assert_eq!(source.format, FileFormat::Csv);
+        assert!(source.gzip);
+
+        let source =
+            FileSource::from_path(Path::new("/data/blocks.jsonl")).expect("supported format");
+        assert_eq!(source.format, FileFormat::JsonLines);
+        assert!(!source.gzip);
+
+        let source =
+            FileSource::from_path(Path::new("/data/ethereum__traces__00000_to_00999.parquet"))
+                .expect("supported format");
+        assert_eq!(source.format, FileFormat::Parquet);
+
+        assert!(FileSource::from_path(Path::new("/data/README.md")).is_none());
+    }
+
+    #[test]
+    fn read_gzip_csv_traces() {
+        let path = std::env::temp_dir().join("zkscan_etl_read_gzip_csv_traces.csv.gz");
+        let mut encoder = GzEncoder::new(File::create(&path).unwrap(), Compression::default());
+        encoder
+            .write_all(
+                b"block_number,transaction_hash,transaction_index,from_address,to_address,value,input,output,trace_type,call_type,reward_type,gas,gas_used,subtraces,trace_address,error,status\n\
+                  100,0x5a1f3a7e6fbcd2b4d7c0e3b9a1d2c4e5f6a7b8c9d0e1f2a3b4c5d6e7f8a9b0c1,3,0x00000000000000000000000000000000000000aa,0x0000000000000000000000000000000000000008,0,0x1234,0x,call,staticcall,,1000,500,0,\"0,1\",,1\n",
+            )
+            .unwrap();
+        encoder.finish().unwrap();
+
+        let read = Arc::new(AtomicU64::new(0));
+        let source = FileSource::from_path(&path).unwrap();
+        let traces = source
+            .records::<Trace>(read.clone())
+            .unwrap()
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        std::fs::remove_file(&path).ok();
+
+        assert_eq!(traces.len(), 1);
+        assert_eq!(traces[0].block_number, 100);
+        assert_eq!(traces[0].transaction_index, Some(3));
+        assert_eq!(traces[0].trace_address, vec![0, 1]);
+        assert_eq!(traces[0].call_type.as_deref(), Some("staticcall"));
+        assert!(traces[0].error.is_none());
+        assert!(read.load(Ordering::Relaxed) > 0);
+    }
+
+    #[test]
+    fn only_decimal_cells_are_numbers() {
+        assert_eq!(text_value("gas", "12").unwrap(), Value::from(12));
+        assert!(text_value("gas", "0.5").unwrap().is_number());
+        assert_eq!(text_value("input", ".").unwrap(), Value::from("."));
+        assert_eq!(text_value("input", "1.2.3").unwrap(), Value::from("1.2.3"));
+        assert_eq!(text_value("input", "0x12").unwrap(), Value::from("0x12"));
+    }
+
+    #[test]
+    fn bad_rows_do_not_fail_the_file() {
+        let path = std::env::temp_dir().join("zkscan_etl_bad_rows_do_not_fail_the_file.csv");
+        std::fs::write(
+            &path,
+            "block_number,transaction_hash,trace_address,subtraces,value\n\
+             1,0x5a1f3a7e6fbcd2b4d7c0e3b9a1d2c4e5f6a7b8c9d0e1f2a3b4c5d6e7f8a9b0c1,,0,\n\
+             1.2.3,0x5a1f3a7e6fbcd2b4d7c0e3b9a1d2c4e5f6a7b8c9d0e1f2a3b4c5d6e7f8a9b0c1,,0,\n\
+             3,0x5a1f3a7e6fbcd2b4d7c0e3b9a1d2c4e5f6a7b8c9d0e1f2a3b4c5d6e7f8a9b0c1,,0,\n",
+        )
+        .unwrap();
+
+        let records = FileSource::from_path(&path)
+            .unwrap()
+            .records::<Trace>(Arc::default())
+            .unwrap()
+            .collect::<Vec<_>>();
+        std::fs::remove_file(&path).ok();
+
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0].as_ref().unwrap().block_number, 1);
+        assert!(records[1].as_ref().unwrap_err().is::<BadRecord>());
+        assert_eq!(records[2].as_ref().unwrap().block_number, 3);
+    }
+}
@@ -0,0 +1,367 @@
+use std::{
+    collections::{hash_map::Entry, HashMap, VecDeque},
+    sync::Arc,
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use ethers::providers::{Http, Middleware, Provider};
+use futures_util::{stream::FuturesUnordered, StreamExt};
+use log::{debug, info};
+use rdkafka::{
+    config::FromClientConfigAndContext,
+    consumer::{Consumer, RebalanceProtocol, StreamConsumer},
+    message::OwnedMessage,
+    ClientConfig, Message, TopicPartitionList,
+};
+use tokio::{
+    select,
+    sync::mpsc::{channel, error::TrySendError, Sender},
+    task::{AbortHandle, JoinSet},
+};
+
+use crate::{
+    config::{KafkaChainConfig, StartOffset, CONFIG},
+    dumper::POSTGRESQL_DUMPER,
+    shutdown::SHUTDOWN,
+};
+
+use super::{
+    ArcConsumer, BlockConsumer, DeadLetterQueue, LogConsumer, StartOffsetContext, TraceConsumer,
+    TransactionConsumer, PARTITION_QUEUE_SIZE,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TopicKind {
+    Traces,
+    Blocks,
+    Transactions,
+    Logs,
+}
+
+/// Queue of the worker of a topic partition
+struct PartitionQueue {
+    tx: Sender<OwnedMessage>,
+    worker: AbortHandle,
+    /// Messages received while the queue was full, the partition is paused until they are
+    /// all queued
+    backlog: VecDeque<OwnedMessage>,
+}
+
+impl PartitionQueue {
+    /// Queue the messages held back while there is room, returns whether none is left
+    fn drain_backlog(&mut self) -> bool {
+        while let Some(m) = self.backlog.pop_front() {
+            match self.tx.try_send(m) {
+                Ok(()) => {}
+                Err(TrySendError::Full(m)) => {
+                    self.backlog.push_front(m);
+                    return false;
+                }
+                // The worker stopped, its error is returned once it is joined
+                Err(TrySendError::Closed(_)) => self.backlog.clear(),
+            }
+        }
+        true
+    }
+}
+
+/// A single consumer for all the topics of a Kafka cluster, dispatching their messages to
+/// the chain handlers by topic name
+pub struct ClusterConsumer {
+    cluster: Option<&'static str>,
+    consumer: ArcConsumer,
+    topics: HashMap<&'static str, (&'static KafkaChainConfig, TopicKind)>,
+}
+
+impl ClusterConsumer {
+    /// A consumer for the topics of the given chains, which must all be on `cluster`
+    pub async fn new(
+        cluster: Option<&'static str>,
+        chains: &[&'static KafkaChainConfig],
+    ) -> Result<Self> {
+        let kafka = CONFIG.kafka_cluster(cluster).ok_or_else(|| {
+            anyhow!(
+                "Kafka cluster {} is not configured",
+                cluster.unwrap_or("default")
+            )
+        })?;
+        let config = ClientConfig::from(kafka);
+        let topics = Self::topics(chains)?;
+        let mut starts = vec![];
+        for (topic, (chain, _)) in &topics {
+            if let Some(start) = chain.options.start_offsets.get(*topic) {
+                starts.push((*topic, Self::resolve_start(chain, start).await?));
+            }
+        }
+        let context = StartOffsetContext::new(
+            &config,
+            starts.iter().map(|(topic, start)| (*topic, start)),
+            cluster,
+            kafka.group_id.as_str(),
+            kafka
+                .options
+                .store_offsets
+                .then_some(kafka.group_id.as_str()),
+        )?;
+        let consumer = StreamConsumer::from_config_and_context(&config, context)?;
+        consumer.subscribe(&topics.keys().copied().collect::<Vec<_>>())?;
+        Ok(Self {
+            cluster,
+            consumer: Arc::new(consumer),
+            topics,
+        })
+    }
+
+    /// Replace a start block by its timestamp in milliseconds
+    async fn resolve_start(chain: &KafkaChainConfig, start: &StartOffset) -> Result<StartOffset> {
+        let StartOffset::Block(number) = start else {
+            return Ok(start.clone());
+        };
+        let rpc_url = chain.options.rpc_url.as_deref().ok_or_else(|| {
+            anyhow!(
+                "Chain {} needs an rpc_url to start from block {}",
+                chain.id,
+                number
+            )
+        })?;
+        let block = Provider::<Http>::try_from(rpc_url)?
+            .get_block(*number)
+            .await?
+            .ok_or_else(|| anyhow!("Start block {} of chain {} not found", number, chain.id))?;
+        info!(
+            "Start block {} of chain {} is at {}",
+            number, chain.id, block.timestamp
+        );
+        Ok(StartOffset::Timestamp(
+            block.timestamp.as_u64() as i64 * 1000,
+        ))
+    }
+
+    /// Check that no topic is used by two of the chains
+    pub fn validate(chains: &[&KafkaChainConfig]) -> Result<()> {
+        Self::topics(chains).map(|_| ())
+    }
+
+    fn topics<'a>(
+        chains: &[&'a KafkaChainConfig],
+    ) -> Result<HashMap<&'a str, (&'a KafkaChainConfig, TopicKind)>> {
+        let mut topics = HashMap::new();
+        for chain in chains {
+            for (topic, kind) in [
+                (&chain.traces_topic, TopicKind::Traces),
+                (&chain.blocks_topic, TopicKind::Blocks),
+                (&chain.options.transactions_topic, TopicKind::Transactions),
+                (&chain.options.logs_topic, TopicKind::Logs),
+            ] {
+                let Some(topic) = topic else {
+                    continue;
+                };
+                if let Some((other, _)) = topics.insert(topic.as_str(), (*chain, kind)) {
+                    bail!(
+                        "Topic {} is used by both chain {} and chain {}",
+                        topic,
+                        other.id,
+                        chain.id
+                    );
+                }
+            }
+        }
+        Ok(topics)
+    }
+
+    /// Route messages to one queue per topic partition, each processed by its own task so
+    /// partitions are decoded and handled concurrently but in order. A partition whose queue
+    /// is full is paused until its worker catches up, without holding the others back. The
+    /// task of a revoked partition is aborted, its new owner reads again what was not
+    /// committed.
+    pub async fn dispatch(self) -> Result<()> {
+        info!(
+            "Starting Kafka consumer for cluster {} on topics {:?}",
+            self.cluster.unwrap_or("default"),
+            self.topics.keys().collect::<Vec<_>>()
+        );
+        let mut dead_letters = HashMap::new();
+        for (chain, _) in self.topics.values() {
+            if let Entry::Vacant(entry) = dead_letters.entry(chain.id) {
+                entry.insert(Arc::new(DeadLetterQueue::new(chain)?));
+            }
+        }
+
+        let mut partitions = HashMap::<(String, i32), PartitionQueue>::new();
+        let mut workers = JoinSet::<Result<()>>::new();
+        // Queues of paused partitions, resolved once they have room again
+        let mut freed = FuturesUnordered::new();
+        let mut stream = self.consumer.stream();
+        loop {
+            select! {
+                msg = stream.next() => {
+                    let Some(msg) = msg else {
+                        break;
+                    };
+                    let m = msg?.detach();
+                    let key = (m.topic().to_string(), m.partition());
+                    let queue = match partitions.entry(key.clone()) {
+                        Entry::Occupied(entry) => entry.into_mut(),
+                        Entry::Vacant(entry) => {
+                            entry.insert(self.spawn_worker(m.topic(), &dead_letters, &mut workers)?)
+                        }
+                    };
+                    let paused = !queue.backlog.is_empty();
+                    queue.backlog.push_back(m);
+                    if !paused && !queue.drain_backlog() {
+                        debug!("Pausing {} partition {}, its queue is full", key.0, key.1);
+                        self.consumer.pause(&Self::partition_list(&key))?;
+                        freed.push(Self::free_slot(key, queue.tx.clone()));
+                    }
+                }
+                Some((key, tx)) = freed.next() => {
+                    // The partition may have been revoked since
+                    let Some(queue) = partitions.get_mut(&key).filter(|q| q.tx.same_channel(&tx)) else {
+                        continue;
+                    };
+                    if queue.drain_backlog() {
+                        debug!("Resuming {} partition {}", key.0, key.1);
+                        self.consumer.resume(&Self::partition_list(&key))?;
+                    } else {
+                        freed.push(Self::free_slot(key, tx));
+                    }
+                }
+                _ = self.consumer.context().assignments.notified() => self.assign_pending().await?,
+                _ = self.consumer.context().revocations.notified() => {
+                    for key in self.consumer.context().take_revoked() {
+                        if let Some(queue) = partitions.remove(&key) {
+                            info!("Stopping worker of {} partition {}", key.0, key.1);
+                            queue.worker.abort();
+                            if !queue.backlog.is_empty() {
+                                // Assigned again later, it must not stay paused
+                                self.consumer.resume(&Self::partition_list(&key)).ok();
+                            }
+                        }
+                    }
+                }
+                Some(result) = workers.join_next() => match result {
+                    Err(e) if e.is_cancelled() => {}
+                    result => result??,
+                },
+                // Workers stop at their next message, flushing what they hold
+                _ = SHUTDOWN.wait() => break,
+            }
+        }
+
+        drop(partitions);
+        while let Some(result) = workers.join_next().await {
+            match result {
+                Err(e) if e.is_cancelled() => {}
+                result => result??,
+            }
+        }
+        Ok(())
+    }
+
+    /// Assign the partitions waiting for their stored offsets and applied starts, then record
+    /// the starts applied to them. The consumer restarts when those can't be loaded or saved.
+    async fn assign_pending(&self) -> Result<()> {
+        let context = self.consumer.context();
+        let cluster = context.cluster.unwrap_or_default();
+        let stored = match context.store_group {
+            Some(group_id) => POSTGRESQL_DUMPER
+                .load_offsets(group_id, cluster)
+                .await
+                .with_context(|| format!("Failed to load stored offsets of group {}", group_id))?,
+            None => HashMap::new(),
+        };
+        let applied = match context.has_starts() {
+            true => POSTGRESQL_DUMPER
+                .load_starts(context.group_id, cluster)
+                .await
+                .with_context(|| {
+                    format!(
+                        "Failed to load applied starts of group {}",
+                        context.group_id
+                    )
+                })?,
+            false => HashMap::new(),
+        };
+        let (assignment, starts) = context.take_pending(&stored, &applied);
+        match self.consumer.rebalance_protocol() {
+            RebalanceProtocol::Cooperative => self.consumer.incremental_assign(&assignment)?,
+            _ => self.consumer.assign(&assignment)?,
+        }
+        POSTGRESQL_DUMPER
+            .save_starts(context.group_id, cluster, &starts)
+            .await
+            .with_context(|| {
+                format!(
+                    "Failed to record applied starts of group {}",
+                    context.group_id
+                )
+            })
+    }
+
+    /// Start the worker of a topic partition, returning its queue
+    fn spawn_worker(
+        &self,
+        topic: &str,
+        dead_letters: &HashMap<u64, Arc<DeadLetterQueue>>,
+        workers: &mut JoinSet<Result<()>>,
+    ) -> Result<PartitionQueue> {
+        let (topic, (chain, kind)) = self
+            .topics
+            .get_key_value(topic)
+            .ok_or_else(|| anyhow!("Message from unknown topic {}", topic))?;
+        let dead_letters = dead_letters[&chain.id].clone();
+        let consumer = self.consumer.clone();
+        let (tx, rx) = channel(PARTITION_QUEUE_SIZE);
+        let worker = match kind {
+            TopicKind::Traces => workers.spawn(TraceConsumer::process_partition(
+                chain,
+                topic,
+                consumer,
+                dead_letters,
+                rx,
+            )),
+            TopicKind::Blocks => workers.spawn(BlockConsumer::process_partition(
+                chain,
+                topic,
+                consumer,
+                dead_letters,
+                rx,
+            )),
+            TopicKind::Transactions => workers.spawn(TransactionConsumer::process_partition(
+                chain,
+                topic,
+                consumer,
+                dead_letters,
+                rx,
+            )),
+            TopicKind::Logs => workers.spawn(LogConsumer::process_partition(
+                chain,
+                topic,
+                consumer,
+                dead_letters,
+                rx,
+            )),
+        };
+        Ok(PartitionQueue {
+            tx,
+            worker,
+            backlog: VecDeque::new(),
+        })
+    }
+
+    /// Resolve once the queue has room, or its worker stopped
+    async fn free_slot(
+        key: (String, i32),
+        tx: Sender<OwnedMessage>,
+    ) -> ((String, i32), Sender<OwnedMessage>) {
+        // The slot is released right away, only the dispatch loop sends to the queue
+        tx.reserve().await.ok();
+        (key, tx)
+    }
+
+    fn partition_list((topic, partition): &(String, i32)) -> TopicPartitionList {
+        let mut list = TopicPartitionList::new();
+        list.add_partition(topic, *partition);
+        list
+    }
+}
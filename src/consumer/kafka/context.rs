@@ -0,0 +1,411 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use anyhow::{bail, Result};
+use log::info;
+use rdkafka::{
+    client::NativeClient,
+    consumer::{BaseConsumer, Consumer, ConsumerContext, DefaultConsumerContext},
+    types::RDKafkaRespErr,
+    ClientConfig, ClientContext, Offset, TopicPartitionList,
+};
+use tokio::sync::Notify;
+
+use crate::config::StartOffset;
+
+const METADATA_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Start offset revision applied to a topic partition
+pub type AppliedStart = ((String, i32), String);
+
+/// Ownership of an assigned partition by the consumer, it ends once the partition is revoked
+/// and the offsets read under it must not be committed anymore
+#[derive(Debug, Clone)]
+pub struct Lease(Arc<AtomicBool>);
+
+impl Lease {
+    pub fn new() -> Self {
+        Self(Arc::new(AtomicBool::new(true)))
+    }
+
+    pub fn is_held(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    fn end(&self) {
+        self.0.store(false, Ordering::Relaxed);
+    }
+}
+
+impl Default for Lease {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Consumer context moving partitions to the configured start offset once per revision of
+/// it, as recorded in Postgres, otherwise they resume from the committed offsets. When the
+/// group stores its offsets, partitions resume from the stored ones. Partitions are then only
+/// assigned once the stored offsets and applied starts are loaded, outside of the rebalance
+/// callback. It also tracks which partitions the consumer currently owns.
+#[derive(Debug, Default)]
+pub struct StartOffsetContext {
+    offsets: HashMap<(String, i32), Offset>,
+    /// Offset of the partitions not listed in `offsets`, by topic
+    defaults: HashMap<String, Offset>,
+    /// Revision of each topic's start offset
+    revisions: HashMap<String, String>,
+    /// Name of the consumer's cluster, `None` for the default one
+    pub cluster: Option<&'static str>,
+    pub group_id: &'static str,
+    /// Consumer group whose offsets are stored in Postgres
+    pub store_group: Option<&'static str>,
+    leases: Mutex<HashMap<(String, i32), Lease>>,
+    /// Partitions revoked since the last `take_revoked`
+    revoked: Mutex<Vec<(String, i32)>>,
+    /// Notified whenever partitions are revoked
+    pub revocations: Notify,
+    /// Partitions to assign once their stored offsets and applied starts are loaded
+    pending: Mutex<Vec<(String, i32)>>,
+    /// Notified whenever partitions wait to be assigned
+    pub assignments: Notify,
+}
+
+impl StartOffsetContext {
+    /// Resolve the start offset of each topic into partition offsets, timestamps are looked
+    /// up on the brokers. Blocks must already be resolved into their timestamp.
+    pub fn new<'a>(
+        config: &ClientConfig,
+        starts: impl IntoIterator<Item = (&'a str, &'a StartOffset)>,
+        cluster: Option<&'static str>,
+        group_id: &'static str,
+        store_group: Option<&'static str>,
+    ) -> Result<Self> {
+        let mut context = Self {
+            cluster,
+            group_id,
+            store_group,
+            ..Default::default()
+        };
+        for (topic, start) in starts {
+            context
+                .revisions
+                .insert(topic.to_string(), start.revision());
+            match start {
+                StartOffset::Earliest => {
+                    context
+                        .defaults
+                        .insert(topic.to_string(), Offset::Beginning);
+                }
+                StartOffset::Latest => {
+                    context.defaults.insert(topic.to_string(), Offset::End);
+                }
+                StartOffset::Offsets(offsets) => context.offsets.extend(
+                    offsets
+                        .iter()
+                        .map(|(p, o)| ((topic.to_string(), *p), Offset::Offset(*o))),
+                ),
+                StartOffset::Timestamp(timestamp) => {
+                    let consumer: BaseConsumer = config.create()?;
+                    let metadata = consumer.fetch_metadata(Some(topic), METADATA_TIMEOUT)?;
+                    let mut timestamps = TopicPartitionList::new();
+                    for partition in metadata.topics().iter().flat_map(|t| t.partitions()) {
+                        timestamps.add_partition_offset(
+                            topic,
+                            partition.id(),
+                            Offset::Offset(*timestamp),
+                        )?;
+                    }
+                    context.offsets.extend(
+                        consumer
+                            .offsets_for_times(timestamps, METADATA_TIMEOUT)?
+                            .elements_for_topic(topic)
+                            .iter()
+                            .map(|e| ((topic.to_string(), e.partition()), e.offset())),
+                    );
+                }
+                StartOffset::Block(number) => {
+                    bail!("Start block {} of {} has no timestamp", number, topic)
+                }
+            }
+        }
+        Ok(context)
+    }
+
+    /// Whether the topics have start offsets, whose applied revisions must be loaded
+    pub fn has_starts(&self) -> bool {
+        !self.revisions.is_empty()
+    }
+
+    /// Whether assignments wait for the consumer to load their offsets
+    fn deferred(&self) -> bool {
+        self.store_group.is_some() || self.has_starts()
+    }
+
+    /// Set the stored offset of the newly assigned partitions, or the start offset of those
+    /// without one whose `applied` revision differs. Returns the start revisions to record
+    /// as applied.
+    fn apply(
+        &self,
+        assignment: &mut TopicPartitionList,
+        stored: &HashMap<(String, i32), i64>,
+        applied: &HashMap<(String, i32), String>,
+    ) -> Vec<AppliedStart> {
+        let mut revisions = vec![];
+        for key in assignment
+            .elements()
+            .iter()
+            .map(|e| (e.topic().to_string(), e.partition()))
+            .collect::<Vec<_>>()
+        {
+            let revision = self
+                .revisions
+                .get(&key.0)
+                .filter(|revision| applied.get(&key) != Some(*revision));
+            let start = revision.and_then(|_| {
+                self.offsets
+                    .get(&key)
+                    .or_else(|| self.defaults.get(&key.0))
+                    .copied()
+            });
+            let offset = stored.get(&key).copied().map(Offset::Offset).or(start);
+            if let Some(offset) = offset {
+                info!("Starting {} partition {} at {:?}", key.0, key.1, offset);
+                assignment
+                    .set_partition_offset(&key.0, key.1, offset)
+                    .expect("assigned partition is in the list");
+            }
+            if let Some(revision) = revision {
+                revisions.push((key, revision.clone()));
+            }
+        }
+        revisions
+    }
+
+    /// Lease of an assigned partition
+    pub fn lease(&self, topic: &str, partition: i32) -> Lease {
+        self.leases
+            .lock()
+            .expect("poisoned lease lock")
+            .get(&(topic.to_string(), partition))
+            .cloned()
+            // Messages are only received from assigned partitions
+            .unwrap_or_default()
+    }
+
+    fn assign(&self, assignment: &TopicPartitionList) {
+        let mut leases = self.leases.lock().expect("poisoned lease lock");
+        for e in assignment.elements() {
+            leases.insert((e.topic().to_string(), e.partition()), Lease::new());
+        }
+    }
+
+    fn revoke(&self, revoked: &TopicPartitionList) {
+        let mut leases = self.leases.lock().expect("poisoned lease lock");
+        let keys = revoked
+            .elements()
+            .iter()
+            .map(|e| (e.topic().to_string(), e.partition()))
+            .collect::<Vec<_>>();
+        for key in &keys {
+            if let Some(lease) = leases.remove(key) {
+                lease.end();
+            }
+        }
+        info!("Partitions revoked: {:?}", keys);
+        self.revoked
+            .lock()
+            .expect("poisoned lease lock")
+            .extend(keys);
+        self.revocations.notify_one();
+    }
+
+    /// Partitions revoked since the last call
+    pub fn take_revoked(&self) -> Vec<(String, i32)> {
+        std::mem::take(&mut *self.revoked.lock().expect("poisoned lease lock"))
+    }
+
+    /// Partitions waiting to be assigned, set from `stored` and `applied` as in `apply` and
+    /// leased. They are to be assigned to the consumer right away, then the returned start
+    /// revisions recorded.
+    pub fn take_pending(
+        &self,
+        stored: &HashMap<(String, i32), i64>,
+        applied: &HashMap<(String, i32), String>,
+    ) -> (TopicPartitionList, Vec<AppliedStart>) {
+        let mut assignment = TopicPartitionList::new();
+        for (topic, partition) in
+            std::mem::take(&mut *self.pending.lock().expect("poisoned lease lock"))
+        {
+            assignment.add_partition(&topic, partition);
+        }
+        let revisions = self.apply(&mut assignment, stored, applied);
+        self.assign(&assignment);
+        (assignment, revisions)
+    }
+}
+
+impl ClientContext for StartOffsetContext {}
+
+impl ConsumerContext for StartOffsetContext {
+    fn rebalance(
+        &self,
+        native_client: &NativeClient,
+        err: RDKafkaRespErr,
+        tpl: &mut TopicPartitionList,
+    ) {
+        match err {
+            // The consumer waits for the assignment, it can't be loaded from here without
+            // blocking the runtime
+            RDKafkaRespErr::RD_KAFKA_RESP_ERR__ASSIGN_PARTITIONS if self.deferred() => {
+                self.pending.lock().expect("poisoned lease lock").extend(
+                    tpl.elements()
+                        .iter()
+                        .map(|e| (e.topic().to_string(), e.partition())),
+                );
+                self.assignments.notify_one();
+                return;
+            }
+            RDKafkaRespErr::RD_KAFKA_RESP_ERR__ASSIGN_PARTITIONS => self.assign(tpl),
+            RDKafkaRespErr::RD_KAFKA_RESP_ERR__REVOKE_PARTITIONS => {
+                // Never assigned
+                self.pending
+                    .lock()
+                    .expect("poisoned lease lock")
+                    .retain(|(topic, partition)| tpl.find_partition(topic, *partition).is_none());
+                self.revoke(tpl);
+            }
+            _ => {}
+        }
+        DefaultConsumerContext.rebalance(native_client, err, tpl);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn start_partitions_once() {
+        let config = ClientConfig::new();
+        let start = StartOffset::Offsets(HashMap::from([(0, 1200)]));
+        let context = StartOffsetContext::new(
+            &config,
+            [("traces", &start), ("blocks", &StartOffset::Latest)],
+            None,
+            "etl",
+            None,
+        )
+        .unwrap();
+
+        let mut assignment = TopicPartitionList::new();
+        assignment.add_partition("traces", 0);
+        assignment.add_partition("traces", 1);
+        assignment.add_partition("blocks", 0);
+        let applied = context.apply(&mut assignment, &HashMap::new(), &HashMap::new());
+        assert_eq!(
+            assignment.find_partition("traces", 0).unwrap().offset(),
+            Offset::Offset(1200)
+        );
+        assert_eq!(
+            assignment.find_partition("traces", 1).unwrap().offset(),
+            Offset::Invalid
+        );
+        assert_eq!(
+            assignment.find_partition("blocks", 0).unwrap().offset(),
+            Offset::End
+        );
+        assert_eq!(applied.len(), 3);
+
+        // Once applied, partitions resume from the committed offset
+        let applied = applied.into_iter().collect::<HashMap<_, _>>();
+        let mut assignment = TopicPartitionList::new();
+        assignment.add_partition("traces", 0);
+        assert!(context
+            .apply(&mut assignment, &HashMap::new(), &applied)
+            .is_empty());
+        assert_eq!(
+            assignment.find_partition("traces", 0).unwrap().offset(),
+            Offset::Invalid
+        );
+
+        // Until their start offset changes
+        let start = StartOffset::Offsets(HashMap::from([(0, 1500)]));
+        let changed =
+            StartOffsetContext::new(&config, [("traces", &start)], None, "etl", None).unwrap();
+        let mut assignment = TopicPartitionList::new();
+        assignment.add_partition("traces", 0);
+        assert_eq!(
+            changed.apply(&mut assignment, &HashMap::new(), &applied),
+            vec![(("traces".to_string(), 0), start.revision())]
+        );
+        assert_eq!(
+            assignment.find_partition("traces", 0).unwrap().offset(),
+            Offset::Offset(1500)
+        );
+    }
+
+    #[test]
+    fn stored_offsets_win_over_start_offsets() {
+        let config = ClientConfig::new();
+        let start = StartOffset::Offsets(HashMap::from([(0, 1200), (1, 1200)]));
+        let context =
+            StartOffsetContext::new(&config, [("traces", &start)], None, "etl", Some("etl"))
+                .unwrap();
+        context
+            .pending
+            .lock()
+            .unwrap()
+            .extend([("traces".to_string(), 0), ("traces".to_string(), 1)]);
+
+        let (assignment, _) = context.take_pending(
+            &HashMap::from([(("traces".to_string(), 0), 1500)]),
+            &HashMap::new(),
+        );
+        assert_eq!(
+            assignment.find_partition("traces", 0).unwrap().offset(),
+            Offset::Offset(1500)
+        );
+        assert_eq!(
+            assignment.find_partition("traces", 1).unwrap().offset(),
+            Offset::Offset(1200)
+        );
+        assert!(context.lease("traces", 0).is_held());
+        assert_eq!(
+            context
+                .take_pending(&HashMap::new(), &HashMap::new())
+                .0
+                .count(),
+            0
+        );
+    }
+
+    #[test]
+    fn revoked_partitions_lose_their_lease() {
+        let context = StartOffsetContext::default();
+        let mut assignment = TopicPartitionList::new();
+        assignment.add_partition("traces", 0);
+        assignment.add_partition("traces", 1);
+        context.assign(&assignment);
+        let lease = context.lease("traces", 0);
+
+        let mut revoked = TopicPartitionList::new();
+        revoked.add_partition("traces", 0);
+        context.revoke(&revoked);
+        assert!(!lease.is_held());
+        assert!(context.lease("traces", 1).is_held());
+        assert_eq!(context.take_revoked(), vec![("traces".to_string(), 0)]);
+        assert!(context.take_revoked().is_empty());
+
+        // Assigned again under a new lease
+        context.assign(&revoked);
+        assert!(!lease.is_held());
+        assert!(context.lease("traces", 0).is_held());
+    }
+}
@@ -0,0 +1,214 @@
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail, Result};
+use backon::{ConstantBuilder, Retryable};
+use log::{error, warn};
+use rdkafka::{
+    message::{Header, OwnedHeaders},
+    producer::{FutureProducer, FutureRecord},
+};
+
+use crate::{
+    api::STATS,
+    config::{DeadLetterSink, KafkaChainConfig, CONFIG},
+    dumper::POSTGRESQL_DUMPER,
+};
+
+const PRODUCE_TIMEOUT: Duration = Duration::from_secs(30);
+/// Attempts to write a dead letter before the consumer stops, its message is then read again
+/// on restart
+const WRITE_ATTEMPTS: usize = 5;
+
+/// A message that could not be decoded
+#[derive(Debug, Clone)]
+pub struct DeadLetter {
+    pub chain_id: u64,
+    pub topic: String,
+    pub partition: i32,
+    pub offset: i64,
+    pub error: String,
+    pub payload: Vec<u8>,
+}
+
+enum Sink {
+    Topic(String, FutureProducer),
+    /// Quoted table name, possibly schema qualified
+    Table(String),
+}
+
+/// Writes undecodable messages to the chain's dead-letter sink so consuming can go on, until
+/// the chain's error cap is reached by one consumer run
+pub struct DeadLetterQueue {
+    sink: Option<Sink>,
+    max_errors: Option<u64>,
+    /// Dead letters sent since the consumer started
+    errors: AtomicU64,
+}
+
+impl DeadLetterQueue {
+    pub fn new(chain: &KafkaChainConfig) -> Result<Self> {
+        let sink = match &chain.options.dead_letter {
+            None => None,
+            Some(DeadLetterSink::Table(table)) => Some(Sink::Table(Self::quote_table(table)?)),
+            Some(DeadLetterSink::Topic(topic)) => {
+                let config = CONFIG
+                    .kafka_config(chain.options.cluster.as_deref())
+                    .ok_or_else(|| anyhow!("No Kafka cluster for chain {}", chain.id))?;
+                Some(Sink::Topic(topic.clone(), config.create()?))
+            }
+        };
+        Ok(Self {
+            sink,
+            max_errors: chain.options.max_errors,
+            errors: AtomicU64::new(0),
+        })
+    }
+
+    /// Quote a `table` or `schema.table` name as SQL identifiers, only plain names are accepted
+    fn quote_table(table: &str) -> Result<String> {
+        let valid = |part: &str| {
+            part.chars()
+                .next()
+                .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+                && part.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        };
+        let parts = table.split('.').collect::<Vec<_>>();
+        if parts.len() > 2 || !parts.iter().all(|part| valid(part)) {
+            bail!("Invalid dead-letter table name {:?}", table);
+        }
+        Ok(parts
+            .iter()
+            .map(|part| format!("\"{}\"", part))
+            .collect::<Vec<_>>()
+            .join("."))
+    }
+
+    /// Record a bad message, fails once more errors than tolerated were seen since the
+    /// consumer started or when the message can't be written to the sink, so that it is never
+    /// skipped unrecorded
+    pub async fn send(&self, letter: DeadLetter) -> Result<()> {
+        STATS.increment("dead_letters", Some(letter.chain_id)).await;
+        let errors = self.errors.fetch_add(1, Ordering::Relaxed) + 1;
+        warn!(
+            "Dead letter {} partition {} offset {} on chain {}: {}",
+            letter.topic, letter.partition, letter.offset, letter.chain_id, letter.error
+        );
+
+        let backoff = ConstantBuilder::default()
+            .with_delay(Duration::from_secs(2))
+            .with_max_times(WRITE_ATTEMPTS);
+        let written = (|| self.write(&letter))
+            .retry(&backoff)
+            .notify(|e, _| error!("Failed to write dead letter: {:?}", e))
+            .await;
+        if let Err(e) = written {
+            STATS
+                .increment("dead_letter_failures", Some(letter.chain_id))
+                .await;
+            bail!(
+                "Failed to write dead letter {} partition {} offset {} on chain {}: {:?}",
+                letter.topic,
+                letter.partition,
+                letter.offset,
+                letter.chain_id,
+                e
+            );
+        }
+
+        if let Some(max_errors) = self.max_errors.filter(|m| errors > *m) {
+            bail!(
+                "Chain {} exceeded {} undecodable messages, last at {} partition {} offset {}: {}",
+                letter.chain_id,
+                max_errors,
+                letter.topic,
+                letter.partition,
+                letter.offset,
+                letter.error
+            );
+        }
+        Ok(())
+    }
+
+    async fn write(&self, letter: &DeadLetter) -> Result<()> {
+        match &self.sink {
+            None => Ok(()),
+            Some(Sink::Table(table)) => POSTGRESQL_DUMPER.insert_dead_letter(table, letter).await,
+            Some(Sink::Topic(topic, producer)) => {
+                let partition = letter.partition.to_string();
+                let offset = letter.offset.to_string();
+                let headers = OwnedHeaders::new()
+                    .insert(Header {
+                        key: "topic",
+                        value: Some(&letter.topic),
+                    })
+                    .insert(Header {
+                        key: "partition",
+                        value: Some(&partition),
+                    })
+                    .insert(Header {
+                        key: "offset",
+                        value: Some(&offset),
+                    })
+                    .insert(Header {
+                        key: "error",
+                        value: Some(&letter.error),
+                    });
+                let key = format!("{}:{}:{}", letter.topic, partition, offset);
+                producer
+                    .send(
+                        FutureRecord::to(topic)
+                            .key(&key)
+                            .payload(&letter.payload)
+                            .headers(headers),
+                        PRODUCE_TIMEOUT,
+                    )
+                    .await
+                    .map(|_| ())
+                    .map_err(|(e, _)| e.into())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn stop_after_max_errors() {
+        let queue = DeadLetterQueue {
+            sink: None,
+            max_errors: Some(1),
+            errors: AtomicU64::new(0),
+        };
+        let letter = DeadLetter {
+            chain_id: 90_001,
+            topic: "traces".to_string(),
+            partition: 0,
+            offset: 10,
+            error: "invalid payload".to_string(),
+            payload: b"{".to_vec(),
+        };
+
+        assert!(queue.send(letter.clone()).await.is_ok());
+        assert!(queue.send(letter).await.is_err());
+    }
+
+    #[test]
+    fn quote_table_names() {
+        assert_eq!(
+            DeadLetterQueue::quote_table("dead_letters").unwrap(),
+            "\"dead_letters\""
+        );
+        assert_eq!(
+            DeadLetterQueue::quote_table("etl.dead_letters_2").unwrap(),
+            "\"etl\".\"dead_letters_2\""
+        );
+        assert!(DeadLetterQueue::quote_table("dead_letters; DROP TABLE blocks").is_err());
+        assert!(DeadLetterQueue::quote_table("a.b.c").is_err());
+        assert!(DeadLetterQueue::quote_table("").is_err());
+    }
+}
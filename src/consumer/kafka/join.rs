@@ -0,0 +1,474 @@
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    sync::Mutex,
+};
+
+use ethers::types::{Address, H256};
+use once_cell::sync::Lazy;
+
+use crate::{
+    config::{Chain, ChainFamily},
+    registry::CHAINS,
+    types::{
+        EtlResult, L1Data, Log, LogWithChainId, Transaction, TransactionDetails, UnmatchedDetails,
+        UnmatchedLog,
+    },
+};
+
+use super::TopicCommiter;
+
+/// Blocks kept in memory to match transactions and logs when the chain doesn't set it
+const DEFAULT_JOIN_WINDOW: u64 = 256;
+
+pub static TRANSACTION_JOIN: Lazy<TransactionJoin> = Lazy::new(TransactionJoin::default);
+
+/// Matches the records of the transactions and logs topics with the transaction results of
+/// the traces, whichever comes first waits for the other as long as it is within the join
+/// window of the newest block of the traces. Waiting records are only in memory, their
+/// offsets are held back from the commits until they are matched or leave the window.
+/// Records whose transaction result is not in memory, as for traces handled before a restart
+/// or by another consumer, are also applied to the stored transaction when there is one.
+#[derive(Debug, Default)]
+pub struct TransactionJoin(Mutex<HashMap<u64, ChainJoin>>);
+
+/// How the records of a chain are joined
+#[derive(Debug, Clone, Copy)]
+pub struct JoinConfig {
+    pub window: u64,
+    pub family: ChainFamily,
+}
+
+/// Cluster, topic and partition a waiting record was read from
+type PartitionKey = (Option<&'static str>, &'static str, i32);
+
+/// Where a waiting record was read from, so its offset is not committed
+type Source = (PartitionKey, i64);
+
+#[derive(Debug, Default)]
+struct ChainJoin {
+    /// Newest block of the traces, the details and logs topics may run ahead of it
+    traces_block: u64,
+    details: HashMap<H256, (TransactionDetails, Source)>,
+    logs: HashMap<H256, Vec<(Log, Source)>>,
+    /// Transaction results with the addresses of their degree 0 and 1 contracts
+    matched: HashMap<H256, (Transaction, HashSet<Address>)>,
+    /// Offsets of the waiting records per partition
+    waiting: HashMap<PartitionKey, BTreeSet<i64>>,
+}
+
+impl ChainJoin {
+    /// Move the window forward with the traces, dropping what fell out of it
+    fn advance(&mut self, block_number: u64, window: u64) {
+        if block_number <= self.traces_block {
+            return;
+        }
+        self.traces_block = block_number;
+        let oldest = self.oldest(window);
+        let waiting = &mut self.waiting;
+        self.details.retain(|_, (d, source)| {
+            let keep = d.block_number >= oldest;
+            if !keep {
+                release(waiting, source);
+            }
+            keep
+        });
+        self.logs.retain(|_, logs| {
+            logs.retain(|(l, source)| {
+                let keep = l.block_number >= oldest;
+                if !keep {
+                    release(waiting, source);
+                }
+                keep
+            });
+            !logs.is_empty()
+        });
+        self.matched.retain(|_, (t, _)| t.block_number >= oldest);
+    }
+
+    /// Oldest block still inside the window
+    fn oldest(&self, window: u64) -> u64 {
+        self.traces_block.saturating_sub(window)
+    }
+
+    fn wait(&mut self, commiter: &TopicCommiter) -> Source {
+        let key = (commiter.cluster, commiter.topic_id, commiter.partition);
+        self.waiting.entry(key).or_default().insert(commiter.offset);
+        (key, commiter.offset)
+    }
+}
+
+fn release(waiting: &mut HashMap<PartitionKey, BTreeSet<i64>>, (key, offset): &Source) {
+    if let Some(offsets) = waiting.get_mut(key) {
+        offsets.remove(offset);
+        if offsets.is_empty() {
+            waiting.remove(key);
+        }
+    }
+}
+
+impl TransactionJoin {
+    pub fn config(chain_id: u64) -> JoinConfig {
+        match CHAINS.get(chain_id) {
+            Some(Chain::Kafka(c)) => JoinConfig {
+                window: c.options.join_window.unwrap_or(DEFAULT_JOIN_WINDOW),
+                family: c.family(),
+            },
+            _ => JoinConfig {
+                window: DEFAULT_JOIN_WINDOW,
+                family: ChainFamily::from_chain_id(chain_id),
+            },
+        }
+    }
+
+    /// Enrich the results of a committed trace tree with the details and logs already
+    /// received, its transaction is kept for the ones still to come
+    pub fn join(
+        &self,
+        chain_id: u64,
+        config: JoinConfig,
+        mut results: Vec<EtlResult>,
+    ) -> Vec<EtlResult> {
+        let contracts = results
+            .iter()
+            .filter_map(|r| match r {
+                EtlResult::Contract(c) => Some(c.address),
+                _ => None,
+            })
+            .collect::<HashSet<_>>();
+
+        let mut chains = self.0.lock().expect("poisoned join lock");
+        let chain = chains.entry(chain_id).or_default();
+        let mut logs = vec![];
+        for result in &mut results {
+            let EtlResult::Transaction(tx) = result else {
+                continue;
+            };
+            chain.advance(tx.block_number, config.window);
+            if tx.block_number < chain.oldest(config.window) {
+                continue;
+            }
+            if let Some((details, source)) = chain.details.remove(&tx.transaction_hash) {
+                release(&mut chain.waiting, &source);
+                enrich(tx, config.family, &details);
+            }
+            for (log, source) in chain
+                .logs
+                .remove(&tx.transaction_hash)
+                .into_iter()
+                .flatten()
+            {
+                release(&mut chain.waiting, &source);
+                if contracts.contains(&log.address) {
+                    logs.push(LogWithChainId { chain_id, log }.into());
+                }
+            }
+            chain
+                .matched
+                .insert(tx.transaction_hash, (tx.clone(), contracts.clone()));
+        }
+        results.extend(logs);
+        results
+    }
+
+    /// Record the details of a transaction read by `commiter`, returns its transaction
+    /// result enriched when it was already found from the traces, otherwise the details to
+    /// apply to its stored row
+    pub fn add_details(
+        &self,
+        chain_id: u64,
+        config: JoinConfig,
+        details: TransactionDetails,
+        commiter: &TopicCommiter,
+    ) -> EtlResult {
+        let mut chains = self.0.lock().expect("poisoned join lock");
+        let chain = chains.entry(chain_id).or_default();
+        if details.block_number < chain.oldest(config.window) {
+            return receipt(chain_id, config.family, &details).into();
+        }
+        match chain.matched.get_mut(&details.hash) {
+            Some((tx, _)) => {
+                enrich(tx, config.family, &details);
+                tx.clone().into()
+            }
+            None => {
+                // Read again after the partition was assigned again
+                if let Some((_, replaced)) = chain.details.remove(&details.hash) {
+                    release(&mut chain.waiting, &replaced);
+                }
+                let unmatched = receipt(chain_id, config.family, &details).into();
+                let source = chain.wait(commiter);
+                chain.details.insert(details.hash, (details, source));
+                unmatched
+            }
+        }
+    }
+
+    /// Record a log read by `commiter`, returns it as a result when its transaction was
+    /// already found from the traces and it was emitted by one of the transaction's contracts,
+    /// or to be matched with its stored transaction when that wasn't found
+    pub fn add_log(
+        &self,
+        chain_id: u64,
+        config: JoinConfig,
+        log: Log,
+        commiter: &TopicCommiter,
+    ) -> Option<EtlResult> {
+        let mut chains = self.0.lock().expect("poisoned join lock");
+        let chain = chains.entry(chain_id).or_default();
+        if log.block_number < chain.oldest(config.window) {
+            return Some(UnmatchedLog { chain_id, log }.into());
+        }
+        match chain.matched.get(&log.transaction_hash) {
+            Some((_, contracts)) => contracts
+                .contains(&log.address)
+                .then(|| LogWithChainId { chain_id, log }.into()),
+            None => {
+                let source = chain.wait(commiter);
+                chain
+                    .logs
+                    .entry(log.transaction_hash)
+                    .or_default()
+                    .push((log.clone(), source));
+                Some(UnmatchedLog { chain_id, log }.into())
+            }
+        }
+    }
+
+    /// Hold the offset of a commiter back to the oldest record of its partition still
+    /// waiting, so it is read again after a restart
+    pub fn held_back(&self, chain_id: u64, mut commiter: TopicCommiter) -> TopicCommiter {
+        let chains = self.0.lock().expect("poisoned join lock");
+        let key = (commiter.cluster, commiter.topic_id, commiter.partition);
+        if let Some(oldest) = chains
+            .get(&chain_id)
+            .and_then(|chain| chain.waiting.get(&key))
+            .and_then(|offsets| offsets.first())
+        {
+            commiter.offset = commiter.offset.min(*oldest);
+        }
+        commiter
+    }
+}
+
+/// Receipt fields of a transaction from its details
+fn receipt(chain_id: u64, family: ChainFamily, details: &TransactionDetails) -> UnmatchedDetails {
+    UnmatchedDetails {
+        chain_id,
+        transaction_hash: details.hash,
+        status: details.status,
+        gas_price: details.gas_price,
+        nonce: Some(details.nonce),
+        effective_gas_price: details.effective_gas_price,
+        fee: details
+            .gas_used
+            .zip(details.effective_gas_price)
+            .map(|(gas, price)| price * gas),
+        l1: L1Data::from_details(family, details),
+        blob_versioned_hashes: details.blob_versioned_hashes.clone(),
+    }
+}
+
+fn enrich(tx: &mut Transaction, family: ChainFamily, details: &TransactionDetails) {
+    let receipt = receipt(tx.chain_id, family, details);
+    tx.status = receipt.status;
+    tx.gas_price = receipt.gas_price;
+    tx.nonce = receipt.nonce;
+    tx.effective_gas_price = receipt.effective_gas_price;
+    tx.fee = receipt.fee;
+    tx.l1 = receipt.l1;
+    tx.blob_versioned_hashes = receipt.blob_versioned_hashes;
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{from_value, json};
+
+    use std::sync::Arc;
+
+    use crate::{consumer::Lease, types::Contract};
+
+    use super::*;
+
+    fn commiter(offset: i64) -> TopicCommiter {
+        TopicCommiter {
+            chain_id: 1,
+            cluster: None,
+            topic_id: "logs",
+            partition: 0,
+            offset,
+            lease: Lease::new(),
+            group_id: None,
+            commit_fn: Arc::new(|_| Ok(())),
+        }
+    }
+
+    fn results(hash: H256, contract: Address) -> Vec<EtlResult> {
+        let contract = Contract {
+            chain_id: 1,
+            address: contract,
+            function_signatures: HashSet::new(),
+            degree: 1,
+            ec_recover_count: 0,
+            ec_add_count: 0,
+            ec_mul_count: 0,
+            ec_pairing_count: 1,
+            ec_pairing_input_sizes: vec![],
+            call: HashSet::new(),
+        };
+        let transaction: Transaction = from_value(json!({
+            "chain_id": 1,
+            "from_address": Address::zero(),
+            "to_address": contract.address,
+            "closest_address": [],
+            "function_signature": "0x00000000",
+            "transaction_hash": hash,
+            "transaction_index": 0,
+            "block_number": 100,
+            "block_timestamp": null,
+            "block_hash": null,
+            "value": "0x0",
+            "input": "0x",
+            "gas_used": {"total": 0, "first_degree": 0, "second_degree": 0},
+            "ec_recover_count": 0,
+            "ec_add_count": 0,
+            "ec_mul_count": 0,
+            "ec_pairing_count": 1,
+            "ec_pairing_input_sizes": [],
+            "ec_recover_addresses": [],
+            "error": null,
+            "status": null,
+            "gas_price": null,
+            "nonce": null,
+            "effective_gas_price": null,
+            "fee": null,
+            "l1": {},
+            "blob_versioned_hashes": [],
+            "point_evaluation_hashes": [],
+        }))
+        .unwrap();
+        vec![contract.into(), transaction.into()]
+    }
+
+    fn log(hash: H256, address: Address, log_index: u32) -> Log {
+        from_value(json!({
+            "log_index": log_index,
+            "transaction_hash": hash,
+            "address": address,
+            "data": "0x",
+            "topics": format!("{:?},{:?}", H256::repeat_byte(1), H256::repeat_byte(2)),
+            "block_number": 100,
+        }))
+        .unwrap()
+    }
+
+    const CONFIG_10: JoinConfig = JoinConfig {
+        window: 10,
+        family: ChainFamily::Ethereum,
+    };
+
+    #[test]
+    fn join_records_before_and_after_traces() {
+        let join = TransactionJoin::default();
+        let verifier = Address::repeat_byte(0xaa);
+        let details = |hash| TransactionDetails {
+            hash,
+            block_number: 100,
+            nonce: 7,
+            gas_price: Some(1_000_000_000u64.into()),
+            status: Some(1),
+            gas_used: Some(21_000),
+            effective_gas_price: Some(1_000_000_000u64.into()),
+            l1_block_number: None,
+            l1_gas_used: None,
+            l1_fee: None,
+            l1_blob_base_fee: None,
+            blob_versioned_hashes: vec![],
+        };
+
+        // Details and logs received before the traces, also applied to a stored transaction
+        let early = H256::repeat_byte(1);
+        assert!(matches!(
+            join.add_details(1, CONFIG_10, details(early), &commiter(1)),
+            EtlResult::UnmatchedDetails(_)
+        ));
+        assert!(matches!(
+            join.add_log(1, CONFIG_10, log(early, verifier, 0), &commiter(2)),
+            Some(EtlResult::UnmatchedLog(_))
+        ));
+        assert!(matches!(
+            join.add_log(
+                1,
+                CONFIG_10,
+                log(early, Address::repeat_byte(0xbb), 1),
+                &commiter(3)
+            ),
+            Some(EtlResult::UnmatchedLog(_))
+        ));
+        let joined = join.join(1, CONFIG_10, results(early, verifier));
+        assert_eq!(joined.len(), 3);
+        let EtlResult::Transaction(tx) = &joined[1] else {
+            panic!("expected transaction");
+        };
+        assert_eq!((tx.status, tx.nonce), (Some(1), Some(7)));
+        let EtlResult::Log(l) = &joined[2] else {
+            panic!("expected log");
+        };
+        assert_eq!(l.log.topics.len(), 2);
+
+        // Details and logs received after the traces
+        let late = H256::repeat_byte(2);
+        assert_eq!(join.join(1, CONFIG_10, results(late, verifier)).len(), 2);
+        let EtlResult::Transaction(tx) =
+            join.add_details(1, CONFIG_10, details(late), &commiter(4))
+        else {
+            panic!("expected enriched transaction");
+        };
+        assert_eq!(tx.gas_price, Some(1_000_000_000u64.into()));
+        assert_eq!(tx.fee, Some(21_000_000_000_000u64.into()));
+        assert!(matches!(
+            join.add_log(1, CONFIG_10, log(late, verifier, 0), &commiter(5)),
+            Some(EtlResult::Log(_))
+        ));
+        assert!(join
+            .add_log(
+                1,
+                CONFIG_10,
+                log(late, Address::repeat_byte(0xbb), 1),
+                &commiter(6)
+            )
+            .is_none());
+
+        // Records older than the window are only applied to their stored transaction
+        let mut old = details(H256::repeat_byte(3));
+        old.block_number = 80;
+        assert!(matches!(
+            join.add_details(1, CONFIG_10, old, &commiter(7)),
+            EtlResult::UnmatchedDetails(_)
+        ));
+        assert!(join.0.lock().unwrap()[&1].details.is_empty());
+    }
+
+    #[test]
+    fn records_ahead_of_traces_wait_and_hold_back_commits() {
+        let join = TransactionJoin::default();
+        let verifier = Address::repeat_byte(0xaa);
+        join.join(1, CONFIG_10, results(H256::repeat_byte(9), verifier));
+
+        // A log far ahead of the traces waits for them, one left behind is dropped later
+        let ahead = H256::repeat_byte(1);
+        let mut ahead_log = log(ahead, verifier, 0);
+        ahead_log.block_number = 150;
+        join.add_log(1, CONFIG_10, ahead_log, &commiter(5));
+        let behind = log(H256::repeat_byte(2), verifier, 0);
+        join.add_log(1, CONFIG_10, behind, &commiter(6));
+        assert_eq!(join.held_back(1, commiter(7).consumed()).offset, 5);
+
+        let mut results = results(ahead, verifier);
+        let EtlResult::Transaction(tx) = &mut results[1] else {
+            panic!("expected transaction");
+        };
+        tx.block_number = 150;
+        assert_eq!(join.join(1, CONFIG_10, results).len(), 3);
+        assert_eq!(join.held_back(1, commiter(7).consumed()).offset, 8);
+    }
+}
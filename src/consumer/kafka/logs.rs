@@ -0,0 +1,48 @@
+use std::pin::Pin;
+
+use anyhow::Result;
+use futures_util::{stream::BoxStream, Future, StreamExt};
+use log::info;
+
+use crate::{channels::CHANNEL, types::Log};
+
+use super::{
+    KafkaConsumer, KafkaStreamConsumer, TopicCommiter, TransactionJoin, IDLE_COMMIT_INTERVAL,
+    TRANSACTION_JOIN,
+};
+
+pub type LogConsumer = KafkaStreamConsumer<Log>;
+
+impl KafkaConsumer for LogConsumer {
+    type Data = Log;
+
+    fn handle_data_stream<'a>(
+        topic_id: &'static str,
+        chain_id: u64,
+        mut stream: BoxStream<'a, Result<(Self::Data, TopicCommiter)>>,
+    ) -> Pin<Box<dyn Future<Output = Result<()>> + Send + 'a>>
+    where
+        Self: Sync + 'a,
+    {
+        Box::pin(async move {
+            let config = TransactionJoin::config(chain_id);
+            let mut uncommitted = 0;
+
+            info!("Starting log consumer for {}", topic_id);
+            while let Some(t) = stream.next().await {
+                let (log, tc) = t?;
+                let result = TRANSACTION_JOIN.add_log(chain_id, config, log, &tc);
+                if result.is_some() || uncommitted >= IDLE_COMMIT_INTERVAL {
+                    let tc = TRANSACTION_JOIN.held_back(chain_id, tc.consumed());
+                    CHANNEL
+                        .send_result(result.into_iter().collect(), tc)
+                        .await?;
+                    uncommitted = 0;
+                } else {
+                    uncommitted += 1;
+                }
+            }
+            Ok(())
+        })
+    }
+}
@@ -1,88 +1,163 @@
-use std::{collections::HashMap, fmt::Debug, marker::PhantomData, pin::Pin, sync::Arc};
+use std::{fmt::Debug, marker::PhantomData, pin::Pin, sync::Arc};
 
-use anyhow::{anyhow, Result};
-use futures_util::{stream::BoxStream, Future, StreamExt};
+use anyhow::Result;
+use futures_util::{
+    future::ready,
+    stream::{poll_fn, BoxStream},
+    Future, StreamExt,
+};
 use rdkafka::{
-    consumer::{CommitMode, Consumer, DefaultConsumerContext, StreamConsumer},
+    consumer::{CommitMode, Consumer, StreamConsumer},
+    message::OwnedMessage,
     util::DefaultRuntime,
     Message, Offset, TopicPartitionList,
 };
 use serde::de::DeserializeOwned;
-use serde_json::from_str;
-use tokio::task::{JoinHandle, JoinSet};
+use tokio::sync::mpsc::Receiver;
 
 mod block;
+mod cluster;
+mod context;
+mod dead_letter;
+mod join;
+mod logs;
+mod offset_store;
+mod payload;
+mod schema_registry;
 mod trace;
+mod transaction;
+mod watermarks;
 pub use block::*;
+pub use cluster::*;
+pub use context::*;
+pub use dead_letter::*;
+pub use join::*;
+pub use logs::*;
+pub use offset_store::*;
+pub use payload::*;
+pub use schema_registry::*;
 pub use trace::*;
+pub use transaction::*;
+pub use watermarks::*;
+
+use crate::{
+    config::{KafkaChainConfig, PayloadFormat},
+    shutdown::SHUTDOWN,
+};
 
-use crate::utils::join_set_else_pending;
+use super::{BadRecord, Commiter, FileRecord};
 
-use super::Commiter;
+type ArcConsumer = Arc<StreamConsumer<StartOffsetContext, DefaultRuntime>>;
 
-type ArcConsumer = Arc<StreamConsumer<DefaultConsumerContext, DefaultRuntime>>;
-pub struct KafkaStreamConsumer<T> {
-    pub consumers: HashMap<&'static str, (u64, ArcConsumer)>,
-    pub _data: PhantomData<T>,
-}
+/// Messages queued per partition before it is paused
+const PARTITION_QUEUE_SIZE: usize = 10_000;
 
-impl<T: DeserializeOwned + Sync + Unpin> KafkaStreamConsumer<T>
+/// Messages without results after which their offset is sent on its own
+const IDLE_COMMIT_INTERVAL: usize = 1_000;
+
+/// Decodes the messages of one topic partition into `T` for its chain handler
+pub struct KafkaStreamConsumer<T>(PhantomData<T>);
+
+impl<T: FileRecord + Sync + Unpin> KafkaStreamConsumer<T>
 where
     Self: KafkaConsumer<Data = T>,
 {
-    pub fn poll() -> JoinHandle<Result<()>> {
-        let s = Self::new();
-        let mut set = JoinSet::<Result<()>>::new();
-        for (topic_id, (chain_id, consumer)) in &s.consumers {
-            let topic_id = *topic_id;
-            let chain_id = *chain_id;
-            let consumer = consumer.clone();
-            set.spawn(async move {
-                let stream = consumer.stream().map(|msg| -> Result<(T, TopicCommiter)> {
-                    let m = msg?;
-                    let payload = m
-                        .payload_view::<str>()
-                        .and_then(|p| p.ok())
-                        .ok_or_else(|| anyhow!("Invalid payload"))?;
-                    let data = from_str::<T>(payload).map_err(|e| {
-                        anyhow!("Serialization Error: {e}, original {payload}, chain id {chain_id}")
-                    })?;
-
-                    Ok((
-                        data,
-                        TopicCommiter {
-                            chain_id,
-                            topic_id,
-                            commit_fn: {
-                                let mut topic_partition = TopicPartitionList::new();
-                                topic_partition.add_partition_offset(
-                                    m.topic(),
-                                    m.partition(),
-                                    Offset::from_raw(m.offset() - 1),
-                                )?;
-                                let consumer = consumer.clone();
-                                Arc::new(move || -> Result<()> {
-                                    consumer.commit(&topic_partition, CommitMode::Async)?;
-                                    Ok(())
-                                })
-                            },
-                            offset: m.offset(),
-                        },
-                    ))
-                });
-                Self::handle_data_stream(topic_id, chain_id, Box::pin(stream)).await?;
-                Ok(())
-            });
-        }
-
-        join_set_else_pending(set)
+    /// Decode and handle the messages of one topic partition in order. On shutdown it stops
+    /// at the next message, the ones still queued are read again on restart.
+    async fn process_partition(
+        chain: &'static KafkaChainConfig,
+        topic_id: &'static str,
+        consumer: ArcConsumer,
+        dead_letters: Arc<DeadLetterQueue>,
+        mut rx: Receiver<OwnedMessage>,
+    ) -> Result<()> {
+        let format = chain.options.payload_format(topic_id);
+        let stream =
+            poll_fn(move |cx| rx.poll_recv(cx))
+                .take_until(SHUTDOWN.wait())
+                .then(move |m| {
+                    let consumer = consumer.clone();
+                    let dead_letters = dead_letters.clone();
+                    async move {
+                        Self::decode(chain.id, topic_id, format, m, consumer, &dead_letters).await
+                    }
+                })
+                .filter_map(|r| ready(r.transpose()));
+        Self::handle_data_stream(topic_id, chain.id, Box::pin(stream)).await
+    }
+
+    /// Decode a message along with its commiter, undecodable messages go to the dead letters.
+    /// Other failures, such as an unreachable schema registry, stop the consumer so the
+    /// message is read again on restart.
+    async fn decode(
+        chain_id: u64,
+        topic_id: &'static str,
+        format: PayloadFormat,
+        m: OwnedMessage,
+        consumer: ArcConsumer,
+        dead_letters: &DeadLetterQueue,
+    ) -> Result<Option<(T, TopicCommiter)>> {
+        let data = match m.payload() {
+            Some(payload) => match decode_payload::<T>(format, payload, &SCHEMA_REGISTRY).await {
+                Err(e) if e.is::<BadRecord>() => Err(format!("Serialization Error: {e}")),
+                data => Ok(data?),
+            },
+            None => Err("Empty payload".to_string()),
+        };
+        let data = match data {
+            Ok(data) => data,
+            Err(error) => {
+                dead_letters
+                    .send(DeadLetter {
+                        chain_id,
+                        topic: m.topic().to_string(),
+                        partition: m.partition(),
+                        offset: m.offset(),
+                        error,
+                        payload: m.payload().unwrap_or_default().to_vec(),
+                    })
+                    .await?;
+                return Ok(None);
+            }
+        };
+
+        Ok(Some((
+            data,
+            TopicCommiter {
+                chain_id,
+                cluster: consumer.context().cluster,
+                topic_id,
+                partition: m.partition(),
+                offset: m.offset(),
+                lease: consumer.context().lease(m.topic(), m.partition()),
+                group_id: consumer.context().store_group,
+                commit_fn: {
+                    let topic = m.topic().to_string();
+                    let partition = m.partition();
+                    Arc::new(move |offset| -> Result<()> {
+                        let mut topic_partition = TopicPartitionList::new();
+                        topic_partition.add_partition_offset(
+                            &topic,
+                            partition,
+                            Offset::Offset(offset),
+                        )?;
+                        // The last commits must be done before the process exits
+                        let mode = match SHUTDOWN.is_triggered() {
+                            true => CommitMode::Sync,
+                            false => CommitMode::Async,
+                        };
+                        consumer.commit(&topic_partition, mode)?;
+                        Ok(())
+                    })
+                },
+            },
+        )))
     }
 }
 
 pub trait KafkaConsumer {
     type Data: DeserializeOwned;
 
-    fn new() -> Self;
     fn handle_data_stream<'a>(
         topic_id: &'static str,
         chain_id: u64,
@@ -95,23 +170,52 @@ pub trait KafkaConsumer {
 #[derive(Clone)]
 pub struct TopicCommiter {
     pub chain_id: u64,
+    pub cluster: Option<&'static str>,
     pub topic_id: &'static str,
+    pub partition: i32,
+    /// Next offset to consume once the results sent along are persisted, the message the
+    /// commiter was created for is not covered until `consumed` is called
     pub offset: i64,
-    pub commit_fn: Arc<dyn Fn() -> Result<()> + Send + Sync>,
+    /// Ownership of the partition when the message was read, nothing is committed once lost
+    pub lease: Lease,
+    /// Consumer group whose offsets are stored in Postgres, if any
+    pub group_id: Option<&'static str>,
+    pub commit_fn: Arc<dyn Fn(i64) -> Result<()> + Send + Sync>,
 }
 
 impl Debug for TopicCommiter {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("TopicCommit")
             .field("chain_id", &self.chain_id)
+            .field("cluster", &self.cluster)
             .field("topic_id", &self.topic_id)
+            .field("partition", &self.partition)
+            .field("offset", &self.offset)
+            .field("group_id", &self.group_id)
             .finish()
     }
 }
 
 impl TopicCommiter {
+    /// Cover the message this commiter was created for as well
+    pub fn consumed(mut self) -> Self {
+        self.offset += 1;
+        self
+    }
+
     pub fn commit(&self) -> Result<()> {
-        (self.commit_fn)()
+        (self.commit_fn)(self.offset)
+    }
+
+    /// Offset to store with the results, when the group's offsets are stored in Postgres
+    pub fn stored(&self) -> Option<StoredOffset> {
+        Some(StoredOffset {
+            group_id: self.group_id?,
+            cluster: self.cluster.unwrap_or_default(),
+            topic: self.topic_id,
+            partition: self.partition,
+            offset: self.offset,
+        })
     }
 }
 
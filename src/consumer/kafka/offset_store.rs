@@ -0,0 +1,53 @@
+use crate::dumper::Insertable;
+
+/// Next offset to consume of a partition for a consumer group, stored in the same
+/// transaction as the results consumed before it. It never moves back, so a consumer
+/// flushing after its partition was revoked can't rewind the new owner.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredOffset {
+    pub group_id: &'static str,
+    /// Empty for the default cluster
+    pub cluster: &'static str,
+    pub topic: &'static str,
+    pub partition: i32,
+    pub offset: i64,
+}
+
+impl Insertable for StoredOffset {
+    const INSERT_QUERY: &'static str =
+        "INSERT INTO kafka_offsets (group_id, cluster, topic, partition, \"offset\") VALUES {values}
+    ON CONFLICT (group_id, cluster, topic, partition) DO UPDATE SET \"offset\" = EXCLUDED.\"offset\"
+    WHERE kafka_offsets.\"offset\" < EXCLUDED.\"offset\"";
+
+    fn value(&self) -> String {
+        format!(
+            "('{}','{}','{}',{},{})",
+            quote(self.group_id),
+            quote(self.cluster),
+            quote(self.topic),
+            self.partition,
+            self.offset
+        )
+    }
+}
+
+fn quote(literal: &str) -> String {
+    literal.replace('\'', "''")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quoted_names() {
+        let offset = StoredOffset {
+            group_id: "etl's",
+            cluster: "",
+            topic: "traces",
+            partition: 3,
+            offset: 42,
+        };
+        assert_eq!(offset.value(), "('etl''s','','traces',3,42)");
+    }
+}
@@ -0,0 +1,334 @@
+use anyhow::{anyhow, bail, Result};
+use prost_reflect::{DynamicMessage, FieldDescriptor, ReflectMessage, Value as ProtoValue};
+use serde_json::{from_str, from_value, Map, Number, Value};
+
+use crate::{
+    config::PayloadFormat,
+    consumer::{hex, text_value, BadRecord, FileRecord},
+};
+
+use super::{RegisteredSchema, SchemaRegistry};
+
+/// First byte of a payload framed by the Confluent serializers
+const MAGIC_BYTE: u8 = 0;
+
+/// Decode a Kafka message payload, Avro and Protobuf payloads are expected in Confluent
+/// wire format: a magic byte, the big endian schema id, then the encoded record. Payloads
+/// that can't be decoded are `BadRecord` errors, failing to fetch their schema is not.
+pub async fn decode_payload<T: FileRecord>(
+    format: PayloadFormat,
+    payload: &[u8],
+    registry: &SchemaRegistry,
+) -> Result<T> {
+    if format == PayloadFormat::Json {
+        return std::str::from_utf8(payload)
+            .map_err(BadRecord::wrap)
+            .and_then(|json| from_str::<T>(json).map_err(BadRecord::wrap));
+    }
+    let (id, data) = schema_id(payload).map_err(BadRecord::wrap)?;
+    let schema = registry.get(id).await?;
+    decode_framed::<T>(format, id, &schema, data).map_err(BadRecord::wrap)
+}
+
+/// Decode the record of a framed payload with its registered schema
+fn decode_framed<T: FileRecord>(
+    format: PayloadFormat,
+    id: u32,
+    schema: &RegisteredSchema,
+    mut data: &[u8],
+) -> Result<T> {
+    let value = match (format, schema) {
+        (PayloadFormat::Avro, RegisteredSchema::Avro(schema)) => {
+            avro_value("", apache_avro::from_avro_datum(schema, &mut data, None)?)?
+        }
+        (PayloadFormat::Protobuf, RegisteredSchema::Protobuf(messages)) => {
+            let indexes = message_indexes(&mut data)?;
+            let mut descriptor = messages
+                .get(indexes[0])
+                .cloned()
+                .ok_or_else(|| anyhow!("Schema {} has no message {}", id, indexes[0]))?;
+            for index in &indexes[1..] {
+                let nested = descriptor.child_messages().nth(*index);
+                descriptor = nested
+                    .ok_or_else(|| anyhow!("Schema {} has no nested message {}", id, index))?;
+            }
+            message_value(&DynamicMessage::decode(descriptor, data)?)?
+        }
+        (format, _) => bail!("Schema {} is not a {:?} schema", id, format),
+    };
+    Ok(from_value::<T>(record_value::<T>(value)?)?)
+}
+
+/// Rename the fields of a decoded record the same way as columnar export files, filling
+/// in the required ones it lacks
+fn record_value<T: FileRecord>(value: Value) -> Result<Value> {
+    let Value::Object(record) = value else {
+        bail!("Decoded payload is not a record");
+    };
+    let mut fields = T::missing_columns()
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v))
+        .collect::<Map<_, _>>();
+    for (name, value) in record {
+        let name = T::COLUMN_ALIASES
+            .iter()
+            .find(|(from, _)| *from == name)
+            .map_or(name.as_str(), |(_, to)| to);
+        fields.insert(name.to_string(), value);
+    }
+    Ok(Value::Object(fields))
+}
+
+fn schema_id(payload: &[u8]) -> Result<(u32, &[u8])> {
+    match payload {
+        [MAGIC_BYTE, a, b, c, d, data @ ..] => Ok((u32::from_be_bytes([*a, *b, *c, *d]), data)),
+        _ => bail!("Payload is not in schema registry wire format"),
+    }
+}
+
+fn zigzag_varint(data: &mut &[u8]) -> Result<i64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let (byte, rest) = data
+            .split_first()
+            .ok_or_else(|| anyhow!("Truncated message indexes"))?;
+        *data = rest;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok((value >> 1) as i64 ^ -((value & 1) as i64));
+        }
+    }
+    bail!("Invalid varint in message indexes")
+}
+
+/// Path to the message type inside the schema file, a lone 0 stands for the first message
+fn message_indexes(data: &mut &[u8]) -> Result<Vec<usize>> {
+    let count = zigzag_varint(data)?;
+    if count == 0 {
+        return Ok(vec![0]);
+    }
+    (0..count)
+        .map(|_| Ok(usize::try_from(zigzag_varint(data)?)?))
+        .collect()
+}
+
+/// Map an Avro value onto the JSON value the serde types expect, `field` is the name of
+/// the enclosing record field
+fn avro_value(field: &str, value: apache_avro::types::Value) -> Result<Value> {
+    use apache_avro::types::Value as Avro;
+
+    Ok(match value {
+        Avro::Null => Value::Null,
+        Avro::Boolean(b) => Value::Bool(b),
+        Avro::Int(i) => i.into(),
+        Avro::Long(i) => i.into(),
+        Avro::Float(f) => Number::from_f64(f as f64).map_or(Value::Null, Value::Number),
+        Avro::Double(f) => Number::from_f64(f).map_or(Value::Null, Value::Number),
+        Avro::String(s) | Avro::Enum(_, s) => text_value(field, &s)?,
+        Avro::Bytes(b) | Avro::Fixed(_, b) => bytes_value(&b),
+        Avro::Union(_, v) => avro_value(field, *v)?,
+        Avro::Array(values) => Value::Array(
+            values
+                .into_iter()
+                .map(|v| avro_value(field, v))
+                .collect::<Result<_>>()?,
+        ),
+        Avro::Record(fields) => Value::Object(
+            fields
+                .into_iter()
+                .map(|(k, v)| Ok((k.clone(), avro_value(&k, v)?)))
+                .collect::<Result<Map<_, _>>>()?,
+        ),
+        Avro::TimestampMillis(ms) => (ms / 1_000).into(),
+        Avro::TimestampMicros(us) => (us / 1_000_000).into(),
+        v => bail!("Unsupported Avro value in field {}: {:?}", field, v),
+    })
+}
+
+/// Map a Protobuf message onto a JSON object, unset optional fields are null
+fn message_value(message: &DynamicMessage) -> Result<Value> {
+    message
+        .descriptor()
+        .fields()
+        .map(|field| {
+            let value = match field.supports_presence() && !message.has_field(&field) {
+                true => Value::Null,
+                false => proto_value(&field, &message.get_field(&field))?,
+            };
+            Ok((field.name().to_string(), value))
+        })
+        .collect::<Result<Map<_, _>>>()
+        .map(Value::Object)
+}
+
+fn proto_value(field: &FieldDescriptor, value: &ProtoValue) -> Result<Value> {
+    Ok(match value {
+        ProtoValue::Bool(b) => Value::Bool(*b),
+        ProtoValue::I32(i) => (*i).into(),
+        ProtoValue::I64(i) => (*i).into(),
+        ProtoValue::U32(i) => (*i).into(),
+        ProtoValue::U64(i) => (*i).into(),
+        ProtoValue::F32(f) => Number::from_f64(*f as f64).map_or(Value::Null, Value::Number),
+        ProtoValue::F64(f) => Number::from_f64(*f).map_or(Value::Null, Value::Number),
+        ProtoValue::String(s) => text_value(field.name(), s)?,
+        ProtoValue::Bytes(b) => bytes_value(b),
+        ProtoValue::EnumNumber(n) => field
+            .kind()
+            .as_enum()
+            .and_then(|e| e.get_value(*n))
+            .map_or(Value::from(*n), |v| Value::String(v.name().to_lowercase())),
+        ProtoValue::Message(m) => message_value(m)?,
+        ProtoValue::List(values) => Value::Array(
+            values
+                .iter()
+                .map(|v| proto_value(field, v))
+                .collect::<Result<_>>()?,
+        ),
+        ProtoValue::Map(_) => bail!("Unsupported Protobuf map in field {}", field.name()),
+    })
+}
+
+/// Binary fields are 0x prefixed hex strings in the serde types, empty ones are unset
+fn bytes_value(bytes: &[u8]) -> Value {
+    match bytes.is_empty() {
+        true => Value::Null,
+        false => Value::String(format!("0x{}", hex(bytes))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use apache_avro::{to_avro_datum, types::Value as Avro, Schema};
+    use ethers::types::U256;
+    use prost::Message;
+
+    use super::*;
+    use crate::{consumer::SchemaType, types::Trace};
+
+    fn framed(id: u32, prefix: &[u8], data: Vec<u8>) -> Vec<u8> {
+        let mut payload = vec![MAGIC_BYTE];
+        payload.extend(id.to_be_bytes());
+        payload.extend(prefix);
+        payload.extend(data);
+        payload
+    }
+
+    #[tokio::test]
+    async fn decode_avro_trace() {
+        let registry = SchemaRegistry::local();
+        let schema = r#"{
+            "type": "record",
+            "name": "Trace",
+            "fields": [
+                {"name": "block_number", "type": "long"},
+                {"name": "subtraces", "type": "int"},
+                {"name": "trace_address", "type": {"type": "array", "items": "int"}},
+                {"name": "to_address", "type": ["null", "string"]},
+                {"name": "value", "type": ["null", "string"]},
+                {"name": "input", "type": "bytes"}
+            ]
+        }"#;
+        registry
+            .register(7, SchemaType::Avro, schema)
+            .await
+            .unwrap();
+
+        let record = Avro::Record(vec![
+            ("block_number".to_string(), Avro::Long(100)),
+            ("subtraces".to_string(), Avro::Int(0)),
+            (
+                "trace_address".to_string(),
+                Avro::Array(vec![Avro::Int(0), Avro::Int(1)]),
+            ),
+            (
+                "to_address".to_string(),
+                Avro::Union(
+                    1,
+                    Box::new(Avro::String(
+                        "0x0808080808080808080808080808080808080808".to_string(),
+                    )),
+                ),
+            ),
+            (
+                "value".to_string(),
+                Avro::Union(1, Box::new(Avro::String("256".to_string()))),
+            ),
+            ("input".to_string(), Avro::Bytes(vec![0x12, 0x34])),
+        ]);
+        let data = to_avro_datum(&Schema::parse_str(schema).unwrap(), record).unwrap();
+
+        let trace = decode_payload::<Trace>(PayloadFormat::Avro, &framed(7, &[], data), &registry)
+            .await
+            .unwrap();
+        assert_eq!(trace.block_number, 100);
+        assert_eq!(trace.trace_address, vec![0, 1]);
+        assert_eq!(trace.value, Some(U256::from(256)));
+        assert_eq!(
+            trace.input.as_ref().map(|i| i.to_vec()),
+            Some(vec![0x12, 0x34])
+        );
+        assert!(trace.to_address.is_some());
+
+        // Bad payloads go to the dead letters, an unknown schema is read again
+        let bad =
+            decode_payload::<Trace>(PayloadFormat::Avro, &framed(7, &[], vec![0xff]), &registry)
+                .await
+                .unwrap_err();
+        assert!(bad.is::<BadRecord>());
+        let unknown =
+            decode_payload::<Trace>(PayloadFormat::Avro, &framed(8, &[], vec![]), &registry)
+                .await
+                .unwrap_err();
+        assert!(!unknown.is::<BadRecord>());
+    }
+
+    #[tokio::test]
+    async fn decode_protobuf_trace() {
+        let registry = SchemaRegistry::local();
+        let schema = r#"
+            syntax = "proto3";
+            message Envelope {}
+            message Trace {
+                uint64 block_number = 1;
+                uint32 subtraces = 2;
+                repeated uint32 trace_address = 3;
+                string from_address = 4;
+                bytes output = 5;
+                optional string error = 6;
+                CallType call_type = 7;
+            }
+            enum CallType {
+                CALL = 0;
+                STATICCALL = 1;
+            }
+        "#;
+        let RegisteredSchema::Protobuf(messages) = &*registry
+            .register(9, SchemaType::Protobuf, schema)
+            .await
+            .unwrap()
+        else {
+            panic!("expected a protobuf schema");
+        };
+
+        let mut message = DynamicMessage::new(messages[1].clone());
+        message.set_field_by_name("block_number", ProtoValue::U64(100));
+        message.set_field_by_name("trace_address", ProtoValue::List(vec![ProtoValue::U32(2)]));
+        message.set_field_by_name(
+            "from_address",
+            ProtoValue::String("0x00000000000000000000000000000000000000aa".to_string()),
+        );
+        message.set_field_by_name("call_type", ProtoValue::EnumNumber(1));
+
+        // One message index pointing at the second message, zigzag encoded
+        let payload = framed(9, &[2, 2], message.encode_to_vec());
+        let trace = decode_payload::<Trace>(PayloadFormat::Protobuf, &payload, &registry)
+            .await
+            .unwrap();
+        assert_eq!(trace.block_number, 100);
+        assert_eq!(trace.trace_address, vec![2]);
+        assert_eq!(trace.call_type.as_deref(), Some("staticcall"));
+        assert!(trace.output.is_none());
+        assert!(trace.error.is_none());
+        assert!(trace.from_address.is_some());
+    }
+}
@@ -0,0 +1,133 @@
+use std::{collections::HashMap, sync::Arc};
+
+use anyhow::{anyhow, Result};
+use once_cell::sync::Lazy;
+use prost_reflect::{DescriptorPool, MessageDescriptor};
+use protox::{
+    file::{ChainFileResolver, File, FileResolver, GoogleFileResolver},
+    Compiler,
+};
+use serde::Deserialize;
+use tokio::sync::RwLock;
+
+use crate::config::CONFIG;
+
+pub static SCHEMA_REGISTRY: Lazy<SchemaRegistry> =
+    Lazy::new(|| SchemaRegistry::new(CONFIG.schema_registry.clone()));
+
+/// Name under which a registered protobuf schema is compiled
+const PROTO_FILE_NAME: &str = "registered.proto";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum SchemaType {
+    #[default]
+    Avro,
+    Protobuf,
+}
+
+#[derive(Debug)]
+pub enum RegisteredSchema {
+    Avro(apache_avro::Schema),
+    /// Top level messages of the schema file, in declaration order
+    Protobuf(Vec<MessageDescriptor>),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SchemaResponse {
+    schema: String,
+    #[serde(default)]
+    schema_type: SchemaType,
+}
+
+/// Client of a Confluent compatible schema registry, caching schemas by id. Without an url
+/// only the schemas registered locally are known.
+#[derive(Debug)]
+pub struct SchemaRegistry {
+    url: Option<String>,
+    client: reqwest::Client,
+    schemas: RwLock<HashMap<u32, Arc<RegisteredSchema>>>,
+}
+
+impl SchemaRegistry {
+    pub fn new(url: Option<String>) -> Self {
+        Self {
+            url: url.map(|u| u.trim_end_matches('/').to_string()),
+            client: reqwest::Client::new(),
+            schemas: RwLock::new(HashMap::new()),
+        }
+    }
+
+    pub fn local() -> Self {
+        Self::new(None)
+    }
+
+    /// Parse `schema` and cache it under `id`
+    pub async fn register(
+        &self,
+        id: u32,
+        schema_type: SchemaType,
+        schema: &str,
+    ) -> Result<Arc<RegisteredSchema>> {
+        let schema = Arc::new(match schema_type {
+            SchemaType::Avro => RegisteredSchema::Avro(apache_avro::Schema::parse_str(schema)?),
+            SchemaType::Protobuf => RegisteredSchema::Protobuf(compile_proto(schema)?),
+        });
+        self.schemas.write().await.insert(id, schema.clone());
+        Ok(schema)
+    }
+
+    pub async fn get(&self, id: u32) -> Result<Arc<RegisteredSchema>> {
+        if let Some(schema) = self.schemas.read().await.get(&id) {
+            return Ok(schema.clone());
+        }
+
+        let url = self
+            .url
+            .as_ref()
+            .ok_or_else(|| anyhow!("Unknown schema {} and no schema registry configured", id))?;
+        let response = self
+            .client
+            .get(format!("{}/schemas/ids/{}", url, id))
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<SchemaResponse>()
+            .await?;
+        self.register(id, response.schema_type, &response.schema)
+            .await
+    }
+}
+
+/// Files resolved from memory, used to compile a schema registry protobuf source
+struct SourceFileResolver {
+    name: &'static str,
+    source: String,
+}
+
+impl FileResolver for SourceFileResolver {
+    fn open_file(&self, name: &str) -> Result<File, protox::Error> {
+        match name == self.name {
+            true => File::from_source(name, &self.source),
+            false => Err(protox::Error::file_not_found(name)),
+        }
+    }
+}
+
+fn compile_proto(source: &str) -> Result<Vec<MessageDescriptor>> {
+    let mut resolver = ChainFileResolver::new();
+    resolver.add(SourceFileResolver {
+        name: PROTO_FILE_NAME,
+        source: source.to_string(),
+    });
+    resolver.add(GoogleFileResolver::new());
+
+    let mut compiler = Compiler::with_file_resolver(resolver);
+    compiler.open_file(PROTO_FILE_NAME)?;
+    let pool: DescriptorPool = compiler.descriptor_pool();
+    let file = pool
+        .get_file_by_name(PROTO_FILE_NAME)
+        .ok_or_else(|| anyhow!("Compiled protobuf schema is missing"))?;
+    Ok(file.messages().collect())
+}
@@ -1,55 +1,26 @@
-use std::{collections::HashMap, marker::PhantomData, pin::Pin, sync::Arc};
+use std::pin::Pin;
 
 use anyhow::Result;
 use futures_util::{stream::BoxStream, Future, StreamExt};
 use log::info;
-use rdkafka::{
-    config::FromClientConfig,
-    consumer::{Consumer, StreamConsumer},
-};
 
 use crate::{
     channels::CHANNEL,
-    config::{Chain, KafkaChainConfig, CONFIG},
+    config::Chain,
+    registry::CHAINS,
     types::{Trace, TraceTree},
 };
 
-use super::{KafkaConsumer, KafkaStreamConsumer, TopicCommiter};
+use super::{
+    KafkaConsumer, KafkaStreamConsumer, TopicCommiter, TransactionJoin, IDLE_COMMIT_INTERVAL,
+    TRANSACTION_JOIN,
+};
 
 pub type TraceConsumer = KafkaStreamConsumer<Trace>;
 
 impl KafkaConsumer for TraceConsumer {
     type Data = Trace;
 
-    fn new() -> Self {
-        let consumers = match CONFIG.kafka_config() {
-            Some(config) => CONFIG
-                .chains
-                .iter()
-                .filter_map(|c| match c {
-                    Chain::Kafka(KafkaChainConfig {
-                        id,
-                        traces_topic: Some(traces_topic),
-                        ..
-                    }) => {
-                        let consumer = StreamConsumer::from_config(&config)
-                            .expect("Failed to create consumer");
-                        consumer
-                            .subscribe(&[&traces_topic])
-                            .expect("Failed to subscribe to topic");
-                        Some((traces_topic.as_str(), (*id, Arc::new(consumer))))
-                    }
-                    _ => None,
-                })
-                .collect::<HashMap<_, _>>(),
-            None => HashMap::new(),
-        };
-        Self {
-            consumers,
-            _data: PhantomData,
-        }
-    }
-
     fn handle_data_stream<'a>(
         topic_id: &'static str,
         chain_id: u64,
@@ -60,14 +31,39 @@ impl KafkaConsumer for TraceConsumer {
     {
         Box::pin(async move {
             let mut trace_tree = TraceTree::new(chain_id);
+            let mut uncommitted = 0;
+            // Commiter of the open transaction's root
+            let mut root = None;
+            let join = match CHAINS.get(chain_id) {
+                Some(Chain::Kafka(c)) => c.options.joins_transactions(),
+                _ => false,
+            };
+            let config = TransactionJoin::config(chain_id);
 
             info!("Starting trace consumer for {}", topic_id);
             while let Some(t) = stream.next().await {
                 let (trace, tpl) = t?;
 
                 if trace.trace_address.is_empty() {
-                    if let Some(results) = trace_tree.commit() {
-                        CHANNEL.send_result(results, tpl);
+                    root = Some(tpl.clone());
+                    // Everything before this new root is done, the offset is also sent now
+                    // and then without results so watermarks move through unrelated traces
+                    match trace_tree.commit() {
+                        Some(results) if join => {
+                            CHANNEL
+                                .send_result(TRANSACTION_JOIN.join(chain_id, config, results), tpl)
+                                .await?;
+                            uncommitted = 0;
+                        }
+                        Some(results) => {
+                            CHANNEL.send_result(results, tpl).await?;
+                            uncommitted = 0;
+                        }
+                        None if uncommitted >= IDLE_COMMIT_INTERVAL => {
+                            CHANNEL.send_result(vec![], tpl).await?;
+                            uncommitted = 0;
+                        }
+                        None => uncommitted += 1,
                     }
 
                     trace_tree.reset(&trace);
@@ -75,6 +71,16 @@ impl KafkaConsumer for TraceConsumer {
 
                 trace_tree.add_trace(trace);
             }
+
+            // The stream ends on shutdown, the open transaction may still miss traces so it
+            // is flushed with its root's offset and read again on restart
+            if let (Some(results), Some(root)) = (trace_tree.commit(), root) {
+                let results = match join {
+                    true => TRANSACTION_JOIN.join(chain_id, config, results),
+                    false => results,
+                };
+                CHANNEL.send_result(results, root).await?;
+            }
             Ok(())
         })
     }
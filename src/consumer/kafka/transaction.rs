@@ -0,0 +1,47 @@
+use std::pin::Pin;
+
+use anyhow::Result;
+use futures_util::{stream::BoxStream, Future, StreamExt};
+use log::info;
+
+use crate::{
+    channels::CHANNEL,
+    types::{Blob, TransactionDetails},
+};
+
+use super::{KafkaConsumer, KafkaStreamConsumer, TopicCommiter, TransactionJoin, TRANSACTION_JOIN};
+
+pub type TransactionConsumer = KafkaStreamConsumer<TransactionDetails>;
+
+impl KafkaConsumer for TransactionConsumer {
+    type Data = TransactionDetails;
+
+    fn handle_data_stream<'a>(
+        topic_id: &'static str,
+        chain_id: u64,
+        mut stream: BoxStream<'a, Result<(Self::Data, TopicCommiter)>>,
+    ) -> Pin<Box<dyn Future<Output = Result<()>> + Send + 'a>>
+    where
+        Self: Sync + 'a,
+    {
+        Box::pin(async move {
+            let config = TransactionJoin::config(chain_id);
+
+            info!("Starting transaction consumer for {}", topic_id);
+            while let Some(t) = stream.next().await {
+                let (details, tc) = t?;
+                // Blobs are kept for every transaction, verifications may come much later
+                let mut results = Blob::from_hashes(
+                    chain_id,
+                    details.hash,
+                    details.block_number,
+                    &details.blob_versioned_hashes,
+                );
+                results.push(TRANSACTION_JOIN.add_details(chain_id, config, details, &tc));
+                let tc = TRANSACTION_JOIN.held_back(chain_id, tc.consumed());
+                CHANNEL.send_result(results, tc).await?;
+            }
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,105 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use log::{debug, info};
+
+use super::{StoredOffset, TopicCommiter};
+
+/// Highest offset to commit per cluster topic partition, tracked while results are buffered
+/// and committed once they are persisted. Partitions revoked in the meantime are skipped.
+#[derive(Debug, Default)]
+pub struct Watermarks {
+    pending: HashMap<(Option<&'static str>, &'static str, i32), TopicCommiter>,
+}
+
+impl Watermarks {
+    pub fn track(&mut self, commiter: TopicCommiter) {
+        let key = (commiter.cluster, commiter.topic_id, commiter.partition);
+        match self.pending.get(&key) {
+            // A partition assigned again restarts from its committed offset
+            Some(current) if current.lease.is_held() && current.offset >= commiter.offset => {}
+            _ => {
+                self.pending.insert(key, commiter);
+            }
+        }
+    }
+
+    /// Watermarks of the groups storing their offsets, to persist with the results
+    pub fn stored(&self) -> Vec<StoredOffset> {
+        self.pending
+            .values()
+            .filter(|commiter| commiter.lease.is_held())
+            .filter_map(TopicCommiter::stored)
+            .collect()
+    }
+
+    /// Commit every tracked watermark, only call once all results received with them are
+    /// persisted
+    pub fn commit(&mut self) -> Result<()> {
+        for ((_, topic, partition), commiter) in self.pending.drain() {
+            if !commiter.lease.is_held() {
+                info!(
+                    "Not committing offset {} of {} partition {}, it was revoked",
+                    commiter.offset, topic, partition
+                );
+                continue;
+            }
+            debug!(
+                "Committing offset {} of {} partition {}",
+                commiter.offset, topic, partition
+            );
+            commiter.commit()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use crate::consumer::Lease;
+
+    use super::*;
+
+    #[test]
+    fn commit_highest_offset_per_partition() {
+        let committed = Arc::new(Mutex::new(vec![]));
+        let on_cluster = |cluster: Option<&'static str>, partition: i32, offset: i64| {
+            let committed = committed.clone();
+            TopicCommiter {
+                chain_id: 1,
+                cluster,
+                topic_id: "traces",
+                partition,
+                offset,
+                lease: Lease::new(),
+                group_id: None,
+                commit_fn: Arc::new(move |offset| {
+                    committed.lock().unwrap().push((cluster, partition, offset));
+                    Ok(())
+                }),
+            }
+        };
+        let commiter = |partition, offset| on_cluster(None, partition, offset);
+
+        let mut watermarks = Watermarks::default();
+        watermarks.track(commiter(0, 10));
+        watermarks.track(commiter(1, 4));
+        watermarks.track(commiter(0, 7));
+        watermarks.track(commiter(0, 12).consumed());
+        // Same topic partition on another cluster
+        watermarks.track(on_cluster(Some("archive"), 0, 3));
+        watermarks.commit().unwrap();
+
+        let mut committed = committed.lock().unwrap().clone();
+        committed.sort();
+        assert_eq!(
+            committed,
+            vec![(None, 0, 13), (None, 1, 4), (Some("archive"), 0, 3)]
+        );
+
+        watermarks.commit().unwrap();
+        assert!(watermarks.pending.is_empty());
+    }
+}
@@ -1,17 +1,26 @@
+mod file;
 mod kafka;
+mod supervisor;
 mod ws;
+pub use file::*;
 pub use kafka::*;
+pub use supervisor::*;
 pub use ws::*;
 
-#[derive(Debug, Clone)]
+use crate::types::Checkpoint;
+
+#[derive(Debug, Clone, Default)]
 pub enum Commiter {
+    #[default]
     None,
     Kafka(TopicCommiter),
+    /// Provider block fully sent, persisted along with its results
+    Checkpoint(Checkpoint),
 }
 
-impl Default for Commiter {
-    fn default() -> Self {
-        Self::None
+impl From<Checkpoint> for Commiter {
+    fn from(checkpoint: Checkpoint) -> Self {
+        Self::Checkpoint(checkpoint)
     }
 }
 
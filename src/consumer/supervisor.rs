@@ -0,0 +1,265 @@
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use backon::{BackoffBuilder, ExponentialBuilder};
+use futures_util::future::pending;
+use log::{error, info, warn};
+use once_cell::sync::Lazy;
+use tokio::{spawn, task::JoinHandle, time::sleep};
+
+use crate::{
+    api::STATS,
+    config::{Chain, KafkaChainConfig, CONFIG},
+    providers::PROVIDER_POOL,
+    registry::CHAINS,
+    shutdown::SHUTDOWN,
+};
+
+use super::{ClusterConsumer, FileConsumer, WebSocketConsumer};
+
+pub static SUPERVISOR: Lazy<ChainSupervisor> = Lazy::new(ChainSupervisor::new);
+
+/// A task running after this long without failing is healthy again, its next restart is
+/// not delayed by the earlier ones
+const HEALTHY_RUN: Duration = Duration::from_secs(300);
+/// Interval at which tasks are checked for having all finished
+const FINISHED_POLL: Duration = Duration::from_secs(1);
+
+/// A consumer task, one per provider or file chain and one per Kafka cluster since the
+/// chains of a cluster share its consumer
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Task {
+    Chain(u64),
+    Cluster(Option<String>),
+}
+
+/// Chains of a task and the handle of its supervising loop
+type RunningTask = (Vec<&'static Chain>, JoinHandle<()>);
+
+/// Runs every running chain of the registry as its own task, restarting it with backoff
+/// when it fails
+#[derive(Debug, Default)]
+pub struct ChainSupervisor {
+    tasks: Mutex<HashMap<Task, RunningTask>>,
+}
+
+impl ChainSupervisor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn poll() -> JoinHandle<Result<()>> {
+        spawn(async move {
+            CHAINS.load().await?;
+            SUPERVISOR.sync();
+            pending::<()>().await;
+            Ok(())
+        })
+    }
+
+    /// Start the tasks of the chains that are running in the registry and stop the others,
+    /// a task whose chains changed is restarted
+    pub fn sync(&self) {
+        if SHUTDOWN.is_triggered() {
+            return;
+        }
+        let mut planned = Self::plan(&CHAINS.running());
+        let mut tasks = self.tasks.lock().expect("poisoned supervisor lock");
+        tasks.retain(|task, (chains, handle)| {
+            let keep = planned.get(task).is_some_and(|planned| {
+                planned.len() == chains.len()
+                    && planned
+                        .iter()
+                        .zip(chains.iter())
+                        .all(|(a, b)| std::ptr::eq(*a, *b))
+            });
+            if !keep {
+                info!("Stopping {}", task);
+                handle.abort();
+                if let Task::Chain(chain_id) = task {
+                    let chain_id = *chain_id;
+                    spawn(async move {
+                        PROVIDER_POOL.evict_ws(chain_id).await;
+                        PROVIDER_POOL.evict_rpc(chain_id).await;
+                    });
+                }
+            }
+            keep
+        });
+
+        planned.retain(|task, _| !tasks.contains_key(task));
+        for (task, chains) in planned {
+            if let Task::Cluster(cluster) = &task {
+                if CONFIG.kafka_config(cluster.as_deref()).is_none() {
+                    warn!("Skipping {}, it is not configured", task);
+                    continue;
+                }
+            }
+            info!("Starting {}", task);
+            let handle = spawn(Self::supervise(task.clone(), chains.clone()));
+            tasks.insert(task, (chains, handle));
+        }
+    }
+
+    /// Wait for every task to stop, once shutdown is triggered
+    pub async fn stop(&self) {
+        let tasks = self
+            .tasks
+            .lock()
+            .expect("poisoned supervisor lock")
+            .drain()
+            .collect::<Vec<_>>();
+        for (task, (_, handle)) in tasks {
+            match handle.await {
+                Ok(()) => info!("Stopped {}", task),
+                Err(e) => error!("{} did not stop cleanly: {:?}", task, e),
+            }
+        }
+    }
+
+    /// Resolve once every task finished on its own, which only the tasks of file chains do
+    /// once ingested
+    pub async fn finished(&self) {
+        loop {
+            sleep(FINISHED_POLL).await;
+            let tasks = self.tasks.lock().expect("poisoned supervisor lock");
+            if !tasks.is_empty() && tasks.values().all(|(_, handle)| handle.is_finished()) {
+                return;
+            }
+        }
+    }
+
+    /// Group chains by the task consuming them, in chain id order
+    fn plan(chains: &[&'static Chain]) -> HashMap<Task, Vec<&'static Chain>> {
+        let mut tasks = HashMap::<Task, Vec<_>>::new();
+        for chain in chains {
+            let task = match chain {
+                Chain::Kafka(c) => Task::Cluster(c.options.cluster.clone()),
+                _ => Task::Chain(chain.chain_id()),
+            };
+            tasks.entry(task).or_default().push(*chain);
+        }
+        for chains in tasks.values_mut() {
+            chains.sort_by_key(|c| c.chain_id());
+        }
+        tasks
+    }
+
+    /// Run a task until shutdown or until its file chain is ingested, with an exponential
+    /// backoff between restarts while it keeps failing
+    async fn supervise(task: Task, chains: Vec<&'static Chain>) {
+        let restart = ExponentialBuilder::default()
+            .with_min_delay(Duration::from_secs(1))
+            .with_max_delay(Duration::from_secs(60))
+            .with_max_times(usize::MAX)
+            .with_jitter();
+        let mut delays = restart.build();
+        loop {
+            let run = Instant::now();
+            match Self::run(&chains).await {
+                Ok(()) if SHUTDOWN.is_triggered() => return,
+                Ok(()) if matches!(chains.first(), Some(Chain::File(_))) => {
+                    info!("{} finished", task);
+                    return;
+                }
+                Ok(()) => warn!("{} stopped", task),
+                Err(e) => error!("{} failed: {:?}", task, e),
+            }
+            for chain in &chains {
+                STATS.increment("restarts", Some(chain.chain_id())).await;
+            }
+
+            if run.elapsed() > HEALTHY_RUN {
+                delays = restart.build();
+            }
+            let delay = delays.next().unwrap_or(Duration::from_secs(60));
+            info!("Restarting {} in {:?}", task, delay);
+            if !SHUTDOWN.sleep(delay).await {
+                return;
+            }
+        }
+    }
+
+    async fn run(chains: &[&'static Chain]) -> Result<()> {
+        match chains.first().copied() {
+            Some(Chain::Provider(chain)) => WebSocketConsumer::consume(chain).await,
+            Some(Chain::File(chain)) => FileConsumer::ingest(chain).await,
+            _ => {
+                let chains = chains
+                    .iter()
+                    .copied()
+                    .filter_map(|c| match c {
+                        Chain::Kafka(c) => Some(c),
+                        _ => None,
+                    })
+                    .collect::<Vec<&'static KafkaChainConfig>>();
+                let cluster = chains.first().and_then(|c| c.options.cluster.as_deref());
+                ClusterConsumer::new(cluster, &chains)
+                    .await?
+                    .dispatch()
+                    .await
+            }
+        }
+    }
+}
+
+impl Display for Task {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Task::Chain(chain_id) => write!(f, "consumer of chain {}", chain_id),
+            Task::Cluster(cluster) => write!(
+                f,
+                "Kafka consumer of cluster {}",
+                cluster.as_deref().unwrap_or("default")
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{FileChainConfig, KafkaChainConfig};
+
+    use super::*;
+
+    #[test]
+    fn one_task_per_chain_or_cluster() {
+        let kafka = |id: u64, cluster: Option<&str>| {
+            let mut chain = KafkaChainConfig {
+                id,
+                traces_topic: Some(format!("traces-{}", id)),
+                blocks_topic: None,
+                options: Default::default(),
+            };
+            chain.options.cluster = cluster.map(str::to_string);
+            &*Box::leak(Box::new(Chain::Kafka(chain)))
+        };
+        let file = &*Box::leak(Box::new(Chain::File(FileChainConfig {
+            id: 5,
+            traces_path: Some("traces".to_string()),
+            blocks_path: None,
+        })));
+
+        let tasks = ChainSupervisor::plan(&[
+            kafka(3, None),
+            file,
+            kafka(1, None),
+            kafka(2, Some("archive")),
+        ]);
+        assert_eq!(tasks.len(), 3);
+        assert_eq!(
+            tasks[&Task::Cluster(None)]
+                .iter()
+                .map(|c| c.chain_id())
+                .collect::<Vec<_>>(),
+            vec![1, 3]
+        );
+        assert_eq!(tasks[&Task::Cluster(Some("archive".to_string()))].len(), 1);
+        assert!(std::ptr::eq(tasks[&Task::Chain(5)][0], file));
+    }
+}
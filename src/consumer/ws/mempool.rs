@@ -0,0 +1,231 @@
+use std::{collections::HashMap, sync::Mutex, time::Duration};
+
+use anyhow::{anyhow, Result};
+use backon::{BackoffBuilder, ExponentialBuilder};
+use ethers::{
+    providers::Middleware,
+    types::{BlockId, GethDebugTracingCallOptions, Transaction as PendingTransaction, H256},
+};
+use futures_util::{future::ready, StreamExt};
+use log::{debug, error, info, warn};
+use tokio::sync::watch;
+
+use crate::{
+    api::STATS,
+    channels::CHANNEL,
+    config::ProviderChainConfig,
+    providers::{RpcProvider, PROVIDER_POOL},
+    shutdown::SHUTDOWN,
+    types::{EtlResult, GethTraceCall, Trace, TraceTree},
+};
+
+use super::WebSocketConsumer;
+
+/// Blocks after which a provisional transaction is no longer expected to be mined
+const PROVISIONAL_BLOCKS: u64 = 10_000;
+
+/// Hashes of the provisional transactions pushed for a chain, with the block they were
+/// expected in, so only their rows are removed once mined
+#[derive(Debug, Default)]
+pub(super) struct ProvisionalTransactions(Mutex<HashMap<H256, u64>>);
+
+impl ProvisionalTransactions {
+    pub(super) fn new(pending: impl IntoIterator<Item = (H256, u64)>) -> Self {
+        Self(Mutex::new(pending.into_iter().collect()))
+    }
+
+    fn insert(&self, hash: H256, block_number: u64) {
+        self.0.lock().unwrap().insert(hash, block_number);
+    }
+
+    /// Remove the provisional transactions mined in block `number`, returning their hashes.
+    /// The ones expected more than `PROVISIONAL_BLOCKS` before are forgotten.
+    pub(super) fn take_mined(&self, number: u64, transactions: &[H256]) -> Vec<H256> {
+        let mut pending = self.0.lock().unwrap();
+        let mined = transactions
+            .iter()
+            .filter(|hash| pending.remove(hash).is_some())
+            .copied()
+            .collect();
+        pending.retain(|_, expected| *expected + PROVISIONAL_BLOCKS > number);
+        mined
+    }
+}
+
+impl WebSocketConsumer {
+    /// Simulate every pending transaction on top of the newest `head` until shutdown,
+    /// reconnecting with exponential backoff whenever the subscription ends
+    pub(super) async fn watch_mempool(
+        chain: &'static ProviderChainConfig,
+        head: watch::Receiver<Option<u64>>,
+        provisional: &ProvisionalTransactions,
+    ) -> Result<()> {
+        info!("Starting ws mempool consumer for {}", chain.id);
+
+        let reconnect = ExponentialBuilder::default()
+            .with_min_delay(Duration::from_secs(1))
+            .with_max_delay(Duration::from_secs(60))
+            .with_max_times(usize::MAX)
+            .with_jitter();
+        let mut delays = reconnect.build();
+
+        loop {
+            let mut received = 0;
+            match Self::subscribe_mempool(chain, &head, provisional, &mut received).await {
+                Ok(()) => warn!("Mempool subscription for chain {} ended", chain.id),
+                Err(e) => error!(
+                    "Mempool subscription for chain {} failed: {:?}",
+                    chain.id, e
+                ),
+            }
+            if SHUTDOWN.is_triggered() {
+                return Ok(());
+            }
+
+            if received > 0 {
+                delays = reconnect.build();
+            }
+            let delay = delays.next().unwrap_or(Duration::from_secs(60));
+            info!(
+                "Resubscribing to mempool of chain {} in {:?}",
+                chain.id, delay
+            );
+            if !SHUTDOWN.sleep(delay).await {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Subscribe to full pending transactions and simulate them concurrently. Returns when
+    /// the subscription stream ends or on shutdown, once the simulations in flight are sent.
+    async fn subscribe_mempool(
+        chain: &'static ProviderChainConfig,
+        head: &watch::Receiver<Option<u64>>,
+        provisional: &ProvisionalTransactions,
+        received: &mut u64,
+    ) -> Result<()> {
+        let ws = PROVIDER_POOL.get_ws(chain.id).await?;
+        let rpc = PROVIDER_POOL.get_rpc(chain.id).await?;
+
+        let stream = ws.subscribe_full_pending_txs().await?;
+        info!("Subscribed to pending transactions for chain {}", chain.id);
+        stream
+            .take_until(SHUTDOWN.wait())
+            .map(|tx| {
+                *received += 1;
+                let rpc = rpc.clone();
+                let head = *head.borrow();
+                async move {
+                    let hash = tx.hash;
+                    (
+                        hash,
+                        Self::simulate(chain, &rpc, head, provisional, tx).await,
+                    )
+                }
+            })
+            .buffer_unordered(chain.options.trace_concurrency.max(1))
+            .for_each(|(hash, result)| {
+                // Pending transactions are often replaced or already mined when simulated
+                if let Err(e) = result {
+                    debug!(
+                        "Failed to simulate {:?} on chain {}: {:?}",
+                        hash, chain.id, e
+                    );
+                }
+                ready(())
+            })
+            .await;
+        Ok(())
+    }
+
+    /// Trace a pending transaction on top of the latest block and push it as a provisional
+    /// transaction when it calls the filtered precompiles. Contracts are only pushed from
+    /// mined transactions, the provisional one is replaced once included.
+    async fn simulate(
+        chain: &ProviderChainConfig,
+        rpc: &RpcProvider,
+        head: Option<u64>,
+        provisional: &ProvisionalTransactions,
+        tx: PendingTransaction,
+    ) -> Result<()> {
+        let head = head.ok_or_else(|| anyhow!("No head received yet"))?;
+        let trace = rpc
+            .debug_trace_call(
+                &tx,
+                Some(BlockId::Number(head.into())),
+                GethDebugTracingCallOptions {
+                    tracing_options: GethTraceCall::option(),
+                    state_overrides: None,
+                    block_overrides: None,
+                },
+            )
+            .await?;
+        let Some(trace) = GethTraceCall::from_geth_trace(trace) else {
+            return Ok(());
+        };
+
+        // Included at the earliest in the next block, its position is unknown until then
+        let mut trace_tree = TraceTree::new(chain.id);
+        for trace in trace
+            .0
+            .into_iter()
+            .filter_map(|inner| Trace::from_call_frame(inner, 0, tx.hash, head + 1))
+        {
+            if trace.trace_address.is_empty() {
+                trace_tree.reset(&trace);
+            }
+            trace_tree.add_trace(trace);
+        }
+
+        let results = trace_tree
+            .commit()
+            .into_iter()
+            .flatten()
+            .filter_map(|result| match result {
+                EtlResult::Transaction(mut provisional) => {
+                    provisional.provisional = true;
+                    provisional.nonce = Some(tx.nonce.as_u64());
+                    provisional.gas_price = tx.gas_price;
+                    provisional.blob_versioned_hashes = tx
+                        .other
+                        .get_deserialized::<Vec<H256>>("blobVersionedHashes")
+                        .and_then(Result::ok)
+                        .unwrap_or_default();
+                    Some(provisional.into())
+                }
+                _ => None,
+            })
+            .collect::<Vec<EtlResult>>();
+        if !results.is_empty() {
+            STATS
+                .increment("provisional_transactions", Some(chain.id))
+                .await;
+            CHANNEL.send_result(results, ()).await?;
+            // Known once sent, so its removal is never dumped before it
+            provisional.insert(tx.hash, head + 1);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn take_mined_provisional_transactions() {
+        let (a, b, c) = (
+            H256::repeat_byte(1),
+            H256::repeat_byte(2),
+            H256::repeat_byte(3),
+        );
+        let provisional = ProvisionalTransactions::new([(a, 10), (b, 10)]);
+        assert_eq!(provisional.take_mined(11, &[a, c]), vec![a]);
+        assert!(provisional.take_mined(12, &[a]).is_empty());
+        // Expected too long ago, no longer tracked
+        provisional.take_mined(10 + PROVISIONAL_BLOCKS, &[]);
+        assert!(provisional
+            .take_mined(11 + PROVISIONAL_BLOCKS, &[b])
+            .is_empty());
+    }
+}
@@ -1,136 +1,631 @@
-use std::time::Duration;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::Duration,
+};
 
-use anyhow::Result;
-use backon::{ConstantBuilder, Retryable};
+use anyhow::{anyhow, bail, Result};
+use backon::{BackoffBuilder, ConstantBuilder, ExponentialBuilder, Retryable};
 use ethers::{
     providers::Middleware,
-    types::{Block as EthersBlock, BlockNumber, H256},
-};
-use futures_util::StreamExt;
-use log::{error, info};
-use tokio::{
-    task::{JoinHandle, JoinSet},
-    time::sleep,
+    types::{BlockNumber, GethTrace, TransactionReceipt, H256},
 };
+use futures_util::{stream, StreamExt, TryStreamExt};
+use log::{debug, error, info, warn};
+use tokio::{sync::watch, time::sleep, try_join};
 
 use crate::{
     channels::CHANNEL,
-    config::{Chain, CONFIG},
-    providers::PROVIDER_POOL,
-    types::{Block, BlockWithChainId, GethTraceCall, Trace, TraceTree},
-    utils::join_set_else_pending,
+    config::{BlockFinality, ProviderChainConfig, TraceMismatch},
+    dumper::POSTGRESQL_DUMPER,
+    providers::{RpcProvider, PROVIDER_POOL},
+    shutdown::SHUTDOWN,
+    types::{
+        Blob, Block, BlockWithChainId, Checkpoint, EtlResult, GethTraceCall, Log, LogWithChainId,
+        MinedTransaction, Trace, TraceTree,
+    },
 };
 
+mod mempool;
+
+use mempool::ProvisionalTransactions;
+
 #[derive(Debug, Clone)]
 pub struct WebSocketConsumer;
 
 impl WebSocketConsumer {
-    pub fn poll() -> JoinHandle<Result<()>> {
-        let mut set = JoinSet::<Result<()>>::new();
-        for cc in &CONFIG.chains {
-            if let Chain::Provider(chain) = cc {
-                set.spawn(async move {
-                    if chain.index_block {
-                        info!("Starting ws block consumer for {}", chain.id);
-                    }
-                    if chain.index_tx {
-                        info!("Starting ws trace consumer for {}", chain.id);
+    /// Follow the chain head, along with its mempool when the chain's `mempool` option is set
+    pub async fn consume(chain: &'static ProviderChainConfig) -> Result<()> {
+        let (heads, head) = watch::channel(None);
+        if !chain.options.mempool {
+            return Self::follow(chain, &heads, &ProvisionalTransactions::default()).await;
+        }
+        let provisional =
+            ProvisionalTransactions::new(POSTGRESQL_DUMPER.load_provisional(chain.id).await?);
+        // Both end on shutdown once what they hold is sent, a failure stops the other
+        try_join!(
+            Self::follow(chain, &heads, &provisional),
+            Self::watch_mempool(chain, head, &provisional)
+        )
+        .map(|_| ())
+    }
+
+    /// Follow the chain head until shutdown, reconnecting with exponential backoff whenever
+    /// the subscription ends or the socket dies. Every new head is published on `heads`.
+    async fn follow(
+        chain: &'static ProviderChainConfig,
+        heads: &watch::Sender<Option<u64>>,
+        provisional: &ProvisionalTransactions,
+    ) -> Result<()> {
+        if chain.index_block {
+            info!("Starting ws block consumer for {}", chain.id);
+        }
+        if chain.index_tx {
+            info!("Starting ws trace consumer for {}", chain.id);
+        }
+
+        let reconnect = ExponentialBuilder::default()
+            .with_min_delay(Duration::from_secs(1))
+            .with_max_delay(Duration::from_secs(60))
+            .with_max_times(usize::MAX)
+            .with_jitter();
+        let mut delays = reconnect.build();
+        let mut trace_tree = TraceTree::new(chain.id);
+        let mut last_block = Self::resume(chain).await?;
+
+        loop {
+            let processed_before = last_block;
+            match Self::subscribe(chain, &mut trace_tree, &mut last_block, heads, provisional).await
+            {
+                Ok(()) => warn!("Block subscription for chain {} ended", chain.id),
+                Err(e) => error!("Block subscription for chain {} failed: {:?}", chain.id, e),
+            }
+            if SHUTDOWN.is_triggered() {
+                return Ok(());
+            }
+            PROVIDER_POOL.evict_ws(chain.id).await;
+
+            // Only keep backing off while no progress is being made
+            if last_block != processed_before {
+                delays = reconnect.build();
+            }
+            let delay = delays.next().unwrap_or(Duration::from_secs(60));
+            info!("Reconnecting ws for chain {} in {:?}", chain.id, delay);
+            if !SHUTDOWN.sleep(delay).await {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Block to resume after, from the chain's newest checkpoint that is still canonical. The
+    /// blocks after it are processed again, a reorg deeper than `max_reorg_depth` fails.
+    async fn resume(chain: &ProviderChainConfig) -> Result<Option<u64>> {
+        let depth = chain.options.max_reorg_depth;
+        let checkpoints = POSTGRESQL_DUMPER.load_checkpoints(chain.id, depth).await?;
+        let Some(newest) = checkpoints.first().map(|c| c.block_number) else {
+            return Ok(None);
+        };
+        let rpc = PROVIDER_POOL.get_rpc(chain.id).await?;
+        for checkpoint in &checkpoints {
+            let canonical = rpc
+                .get_block(checkpoint.block_number)
+                .await?
+                .and_then(|b| b.hash);
+            if canonical != Some(checkpoint.block_hash) {
+                continue;
+            }
+            if checkpoint.block_number < newest {
+                warn!(
+                    "Checkpoint block {} of chain {} is no longer canonical, processing again from block {}",
+                    newest,
+                    chain.id,
+                    checkpoint.block_number + 1
+                );
+            }
+            info!(
+                "Resuming chain {} after block {}",
+                chain.id, checkpoint.block_number
+            );
+            return Ok(Some(checkpoint.block_number));
+        }
+        bail!(
+            "No checkpoint of chain {} within {} blocks of block {} is canonical, the reorg is deeper than max_reorg_depth",
+            chain.id,
+            depth,
+            newest
+        )
+    }
+
+    /// Subscribe to new heads and process every block that became deep enough, catching up
+    /// on any block skipped since `last_block` first. Returns when the subscription stream
+    /// ends or on shutdown, once the block being processed is sent.
+    async fn subscribe(
+        chain: &'static ProviderChainConfig,
+        trace_tree: &mut TraceTree,
+        last_block: &mut Option<u64>,
+        heads: &watch::Sender<Option<u64>>,
+        provisional: &ProvisionalTransactions,
+    ) -> Result<()> {
+        let ws = PROVIDER_POOL.get_ws(chain.id).await?;
+        let rpc = PROVIDER_POOL.get_rpc(chain.id).await?;
+
+        let mut stream = ws
+            .subscribe_blocks()
+            .await?
+            .take_until(Box::pin(SHUTDOWN.wait()));
+        info!("Subscribed to new heads for chain {}", chain.id);
+
+        // Blocks mined while stopped are processed before the new heads
+        if let Some(last) = *last_block {
+            let head = rpc.get_block_number().await?.as_u64();
+            heads.send_replace(Some(head));
+            if let Some(target) = Self::confirmed_block(chain, &rpc, head)
+                .await?
+                .filter(|target| *target > last)
+            {
+                info!(
+                    "Catching up chain {} from block {} to {}",
+                    chain.id,
+                    last + 1,
+                    target
+                );
+                Self::process_blocks(
+                    chain,
+                    &rpc,
+                    last + 1,
+                    target,
+                    trace_tree,
+                    last_block,
+                    provisional,
+                )
+                .await?;
+            }
+        }
+
+        let mut previous_head: Option<u64> = None;
+        while let Some(b) = stream.next().await {
+            let Some(head) = b.number.map(|n| n.as_u64()) else {
+                continue;
+            };
+            // Safe and finalized blocks move by whole epochs, only skipped heads are unusual
+            if let Some(previous) = previous_head.filter(|previous| head > *previous + 1) {
+                warn!(
+                    "Missed heads {} to {} of chain {}",
+                    previous + 1,
+                    head - 1,
+                    chain.id
+                );
+            }
+            previous_head = Some(head);
+            heads.send_replace(Some(head));
+
+            let Some(target) = Self::confirmed_block(chain, &rpc, head).await? else {
+                continue;
+            };
+
+            let Some((from, to)) =
+                Self::blocks_to_process(*last_block, target, chain.options.follows_tip())
+            else {
+                continue;
+            };
+            if from < to {
+                debug!(
+                    "Catching up chain {} from block {} to {}",
+                    chain.id,
+                    from,
+                    to - 1
+                );
+            }
+            Self::process_blocks(chain, &rpc, from, to, trace_tree, last_block, provisional)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Blocks to process once `target` is deep enough, starting after `last_block` so the
+    /// ones skipped since are caught up. `None` when `target` was already processed, unless
+    /// re-announced heads are re-processed when following the tip.
+    fn blocks_to_process(
+        last_block: Option<u64>,
+        target: u64,
+        follows_tip: bool,
+    ) -> Option<(u64, u64)> {
+        match last_block {
+            Some(last) if target > last => Some((last + 1, target)),
+            Some(_) if !follows_tip => None,
+            _ => Some((target, target)),
+        }
+    }
+
+    /// Process blocks `from..=to` in order, stopping early on shutdown
+    async fn process_blocks(
+        chain: &ProviderChainConfig,
+        rpc: &Arc<RpcProvider>,
+        from: u64,
+        to: u64,
+        trace_tree: &mut TraceTree,
+        last_block: &mut Option<u64>,
+        provisional: &ProvisionalTransactions,
+    ) -> Result<()> {
+        for number in from..=to {
+            if SHUTDOWN.is_triggered() {
+                break;
+            }
+            Self::process_block(chain, rpc, number, trace_tree, provisional).await?;
+            *last_block = Some(number);
+        }
+        Ok(())
+    }
+
+    /// The newest block deep enough to be processed given the chain's finality settings
+    async fn confirmed_block(
+        chain: &ProviderChainConfig,
+        rpc: &RpcProvider,
+        head: u64,
+    ) -> Result<Option<u64>> {
+        let tag = match chain.options.finality {
+            Some(BlockFinality::Safe) => BlockNumber::Safe,
+            Some(BlockFinality::Finalized) => BlockNumber::Finalized,
+            None => return Ok(head.checked_sub(chain.options.confirmations)),
+        };
+        let get_tagged_block = || async { rpc.get_block(tag).await };
+        Ok(get_tagged_block
+            .retry(&Self::backoff())
+            .notify(|err, _| error!("Error getting {} block: {:?}", tag, err))
+            .await?
+            .and_then(|b| b.number)
+            .map(|n| n.as_u64()))
+    }
+
+    async fn process_block(
+        chain: &ProviderChainConfig,
+        rpc: &Arc<RpcProvider>,
+        number: u64,
+        trace_tree: &mut TraceTree,
+        provisional: &ProvisionalTransactions,
+    ) -> Result<()> {
+        let block_number = BlockNumber::Number(number.into());
+        let get_block_details = || async {
+            rpc.get_block_with_txs(block_number)
+                .await?
+                .ok_or_else(|| anyhow!("Block {} not found on chain {}", number, chain.id))
+        };
+
+        let block_details = get_block_details
+            .retry(&Self::backoff())
+            .notify(|err, _| error!("Error getting transactions from blocks: {:?}", err))
+            .await?;
+        let transactions = block_details
+            .transactions
+            .iter()
+            .map(|tx| tx.hash)
+            .collect::<Vec<_>>();
+        let blobs = block_details
+            .transactions
+            .iter()
+            .filter_map(|tx| {
+                let hashes = tx
+                    .other
+                    .get_deserialized::<Vec<H256>>("blobVersionedHashes")?
+                    .ok()?;
+                Some((tx.hash, hashes))
+            })
+            .collect::<HashMap<_, _>>();
+        let block = Block::from_ethers(block_details)
+            .ok_or_else(|| anyhow!("Block {} on chain {} is pending", number, chain.id))?;
+        let checkpoint = Checkpoint {
+            chain_id: chain.id,
+            block_number: number,
+            block_hash: block.hash,
+            retained: chain.options.max_reorg_depth,
+        };
+
+        // if index tx, call debug_trace_block_by_number with non top call
+        let mut results = vec![];
+        if chain.index_tx {
+            // sleep to avoid block not found, confirmed blocks are already known by the node
+            if chain.options.follows_tip() {
+                sleep(Duration::from_secs(1)).await;
+            }
+            let traces = Self::trace_block(chain, rpc, number, &transactions).await?;
+            let mut committed = vec![];
+            for trace in transactions
+                .iter()
+                .copied()
+                .enumerate()
+                .zip(traces)
+                .filter_map(|((i, h), t)| {
+                    GethTraceCall::from_geth_trace(t).map(move |trace| {
+                        trace.0.into_iter().map(move |inner| {
+                            Trace::from_call_frame(inner, (i + 1) as u32, h, block.number)
+                        })
+                    })
+                })
+                .flatten()
+                .flatten()
+            {
+                if trace.trace_address.is_empty() {
+                    committed.extend(trace_tree.commit());
+                    trace_tree.reset(&trace);
+                }
+
+                trace_tree.add_trace(trace);
+            }
+            // The block's last transaction is complete too, commit it with the block so its
+            // receipt comes from the same batch
+            committed.extend(trace_tree.commit());
+            trace_tree.clear();
+            results = Self::attach_receipts(chain, rpc, number, committed).await?;
+
+            for result in &mut results {
+                if let EtlResult::Transaction(tx) = result {
+                    if let Some(hashes) = blobs.get(&tx.transaction_hash) {
+                        tx.blob_versioned_hashes = hashes.clone();
                     }
-                    let ws = PROVIDER_POOL.get_ws(chain.id).await?;
-                    let rpc = PROVIDER_POOL.get_rpc(chain.id).await?;
-
-                    let mut stream = ws.subscribe_blocks().await?;
-                    let mut trace_tree = TraceTree::new(chain.id);
-
-                    let backoff = ConstantBuilder::default()
-                        .with_delay(Duration::from_millis(2_000))
-                        .with_max_times(5);
-                    while let Some(b) = stream.next().await {
-                        if let Some(mut block) = Block::from_ethers(b) {
-                            let block_number = BlockNumber::Number(block.number.into());
-                            let get_block_details = || async { rpc.get_block(block_number).await };
-                            let get_traces = || async {
-                                rpc.debug_trace_block_by_number(
-                                    Some(block_number),
-                                    GethTraceCall::option(),
-                                )
-                                .await
-                            };
-
-                            let block_details = get_block_details
-                                .retry(&backoff)
-                                .notify(|err, _| {
-                                    error!("Error getting transactions from blocks: {:?}", err)
-                                })
-                                .await?;
-                            let transactions = block_details
-                                .as_ref()
-                                .map(|b| b.transactions.clone())
-                                .unwrap_or_default();
-                            block.transaction_count = transactions.len() as u32;
-                            if let Some(EthersBlock::<H256> {
-                                size: Some(size), ..
-                            }) = block_details
-                            {
-                                block.size = size.as_u32();
-                            }
+                }
+            }
+            // Every blob is kept, the transactions verifying them may come in later blocks
+            for hash in &transactions {
+                if let Some(hashes) = blobs.get(hash) {
+                    results.extend(Blob::from_hashes(chain.id, *hash, number, hashes));
+                }
+            }
+        }
 
-                            // if index tx, call debug_trace_block_by_number with non top call
-                            if chain.index_tx {
-                                // sleep to avoid block not found
-                                sleep(Duration::from_secs(1)).await;
-                                let traces = get_traces
-                                    .retry(&backoff)
-                                    .notify(|err, _| error!("Error getting traces: {:?}", err))
-                                    .await?;
-                                for trace in transactions
-                                    .into_iter()
-                                    .enumerate()
-                                    .zip(traces)
-                                    .filter_map(|((i, h), t)| {
-                                        GethTraceCall::from_geth_trace(t).map(move |trace| {
-                                            trace.0.into_iter().map(move |inner| {
-                                                Trace::from_call_frame(
-                                                    inner,
-                                                    (i + 1) as u32,
-                                                    h,
-                                                    block.number,
-                                                )
-                                            })
-                                        })
-                                    })
-                                    .flatten()
-                                    .flatten()
-                                {
-                                    if trace.trace_address.is_empty() {
-                                        if let Some(results) = trace_tree.commit() {
-                                            CHANNEL.send_result(results, ());
-                                        }
-
-                                        trace_tree.reset(&trace);
-                                    }
-
-                                    trace_tree.add_trace(trace);
-                                }
-                            }
+        // Provisional transactions that are now mined are replaced, or removed when their
+        // mined version isn't a result
+        results.extend(
+            provisional
+                .take_mined(number, &transactions)
+                .into_iter()
+                .map(|hash| {
+                    EtlResult::from(MinedTransaction {
+                        chain_id: chain.id,
+                        transaction_hash: hash,
+                    })
+                }),
+        );
+
+        if chain.index_block {
+            results.push(
+                BlockWithChainId {
+                    chain_id: chain.id,
+                    block,
+                }
+                .into(),
+            );
+        }
+        // Sent even without results so the checkpoint moves on
+        CHANNEL.send_result(results, checkpoint).await?;
+        Ok(())
+    }
 
-                            if chain.index_block {
-                                CHANNEL.send_result(
-                                    vec![BlockWithChainId {
-                                        chain_id: chain.id,
-                                        block,
-                                    }
-                                    .into()],
-                                    (),
-                                );
+    /// Add receipt data to the transaction results of a block, along with the logs emitted
+    /// by the contracts of each transaction
+    async fn attach_receipts(
+        chain: &ProviderChainConfig,
+        rpc: &Arc<RpcProvider>,
+        number: u64,
+        committed: Vec<Vec<EtlResult>>,
+    ) -> Result<Vec<EtlResult>> {
+        let hashes = committed
+            .iter()
+            .flatten()
+            .filter_map(|r| match r {
+                EtlResult::Transaction(tx) => Some(tx.transaction_hash),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        if hashes.is_empty() {
+            return Ok(committed.into_iter().flatten().collect());
+        }
+        let receipts = Self::receipts(chain, rpc, number, &hashes).await?;
+
+        let mut results = vec![];
+        for mut group in committed {
+            let contracts = group
+                .iter()
+                .filter_map(|r| match r {
+                    EtlResult::Contract(c) => Some(c.address),
+                    _ => None,
+                })
+                .collect::<HashSet<_>>();
+            let mut logs = vec![];
+            for result in &mut group {
+                let EtlResult::Transaction(tx) = result else {
+                    continue;
+                };
+                let Some(receipt) = receipts.get(&tx.transaction_hash) else {
+                    continue;
+                };
+                tx.apply_receipt(chain.family(), receipt);
+                logs.extend(
+                    receipt
+                        .logs
+                        .iter()
+                        .filter(|l| contracts.contains(&l.address))
+                        .cloned()
+                        .filter_map(Log::from_ethers)
+                        .map(|log| {
+                            LogWithChainId {
+                                chain_id: chain.id,
+                                log,
                             }
-                        }
+                            .into()
+                        }),
+                );
+            }
+            results.extend(group);
+            results.extend(logs);
+        }
+        Ok(results)
+    }
+
+    /// Receipts of the given transactions, from a single `eth_getBlockReceipts` call when the
+    /// node supports it
+    async fn receipts(
+        chain: &ProviderChainConfig,
+        rpc: &Arc<RpcProvider>,
+        number: u64,
+        hashes: &[H256],
+    ) -> Result<HashMap<H256, TransactionReceipt>> {
+        match rpc.get_block_receipts(number).await {
+            Ok(receipts) => {
+                return Ok(receipts
+                    .into_iter()
+                    .map(|r| (r.transaction_hash, r))
+                    .collect())
+            }
+            Err(e) => warn!(
+                "Failed to get receipts of block {} on chain {}, getting each receipt: {:?}",
+                number, chain.id, e
+            ),
+        }
+
+        let backoff = Self::backoff();
+        let requests = hashes
+            .iter()
+            .copied()
+            .map(|hash| {
+                let rpc = rpc.clone();
+                let get_receipt = move || {
+                    let rpc = rpc.clone();
+                    async move {
+                        rpc.get_transaction_receipt(hash)
+                            .await?
+                            .ok_or_else(|| anyhow!("Receipt of {:?} not found", hash))
                     }
-                    Ok(())
-                });
+                };
+                get_receipt
+                    .retry(&backoff)
+                    .notify(move |err, _| error!("Error getting receipt of {:?}: {:?}", hash, err))
+            })
+            .collect::<Vec<_>>();
+        stream::iter(requests)
+            .buffered(chain.options.trace_concurrency.max(1))
+            .map_ok(|r| (r.transaction_hash, r))
+            .try_collect()
+            .await
+    }
+
+    fn backoff() -> ConstantBuilder {
+        ConstantBuilder::default()
+            .with_delay(Duration::from_millis(2_000))
+            .with_max_times(5)
+    }
+
+    /// Trace a whole block, one trace per transaction in block order. Falls back to tracing
+    /// each transaction individually when block tracing keeps failing, and checks that
+    /// every transaction got exactly one trace.
+    async fn trace_block(
+        chain: &ProviderChainConfig,
+        rpc: &Arc<RpcProvider>,
+        number: u64,
+        transactions: &[H256],
+    ) -> Result<Vec<GethTrace>> {
+        let get_traces = || async {
+            rpc.debug_trace_block_by_number(
+                Some(BlockNumber::Number(number.into())),
+                GethTraceCall::option(),
+            )
+            .await
+        };
+
+        match get_traces
+            .retry(&Self::backoff())
+            .notify(|err, _| error!("Error getting traces: {:?}", err))
+            .await
+        {
+            Ok(traces) if traces.len() == transactions.len() => Ok(traces),
+            Ok(traces) => match chain.options.on_trace_mismatch {
+                TraceMismatch::Abort => bail!(
+                    "Block {} on chain {} has {} transactions but {} traces",
+                    number,
+                    chain.id,
+                    transactions.len(),
+                    traces.len()
+                ),
+                TraceMismatch::Repair => {
+                    warn!(
+                        "Block {} on chain {} has {} transactions but {} traces, tracing each transaction",
+                        number,
+                        chain.id,
+                        transactions.len(),
+                        traces.len()
+                    );
+                    Self::trace_transactions(chain, rpc, transactions).await
+                }
+            },
+            Err(e) => {
+                warn!(
+                    "Failed to trace block {} on chain {}, tracing each transaction: {:?}",
+                    number, chain.id, e
+                );
+                Self::trace_transactions(chain, rpc, transactions).await
             }
         }
+    }
+
+    async fn trace_transactions(
+        chain: &ProviderChainConfig,
+        rpc: &Arc<RpcProvider>,
+        transactions: &[H256],
+    ) -> Result<Vec<GethTrace>> {
+        let backoff = Self::backoff();
+        let requests = transactions
+            .iter()
+            .copied()
+            .map(|hash| {
+                let rpc = rpc.clone();
+                let get_trace = move || {
+                    let rpc = rpc.clone();
+                    async move {
+                        rpc.debug_trace_transaction(hash, GethTraceCall::option())
+                            .await
+                    }
+                };
+                get_trace
+                    .retry(&backoff)
+                    .notify(move |err, _| error!("Error getting trace of {:?}: {:?}", hash, err))
+            })
+            .collect::<Vec<_>>();
+        let traces = stream::iter(requests)
+            .buffered(chain.options.trace_concurrency.max(1))
+            .try_collect::<Vec<_>>()
+            .await?;
+        Ok(traces)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        join_set_else_pending(set)
+    #[test]
+    fn catch_up_missed_blocks() {
+        // First head after start
+        assert_eq!(
+            WebSocketConsumer::blocks_to_process(None, 100, true),
+            Some((100, 100))
+        );
+        // Next head
+        assert_eq!(
+            WebSocketConsumer::blocks_to_process(Some(100), 101, true),
+            Some((101, 101))
+        );
+        // Heads missed while reconnecting are caught up
+        assert_eq!(
+            WebSocketConsumer::blocks_to_process(Some(100), 105, false),
+            Some((101, 105))
+        );
+        // Re-announced heads only go through again when following the tip
+        assert_eq!(
+            WebSocketConsumer::blocks_to_process(Some(100), 99, true),
+            Some((99, 99))
+        );
+        assert_eq!(
+            WebSocketConsumer::blocks_to_process(Some(100), 100, false),
+            None
+        );
     }
 }
@@ -1,3 +1,5 @@
 mod postgres;
+mod writer;
 
 pub use postgres::*;
+pub use writer::*;
@@ -1,12 +1,19 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 
-use crate::{config::CONFIG, types::EtlResult};
+use crate::{
+    config::{Chain, CONFIG},
+    consumer::{AppliedStart, DeadLetter, StoredOffset},
+    registry::ChainStatus,
+    types::{Checkpoint, EtlResult},
+};
 use anyhow::{Error, Result};
 use deadpool_postgres::{Pool as PostgresPool, Runtime};
+use ethers::types::H256;
 use futures_util::future::OptionFuture;
 use once_cell::sync::Lazy;
 use redis::{AsyncCommands, Client as RedisClient};
 use redis_pool::{RedisPool, SingleRedisPool};
+use serde_json::{from_str, to_string};
 use tokio_postgres::NoTls;
 
 mod insert_tree;
@@ -38,7 +45,14 @@ impl PostgreSQLDumper {
         })
     }
 
-    pub async fn insert_results(&self, results: &[EtlResult]) -> Result<()> {
+    /// Insert results along with the checkpoints and Kafka offsets they complete, in a
+    /// single transaction
+    pub async fn insert_results(
+        &self,
+        results: &[EtlResult],
+        checkpoints: &[Checkpoint],
+        offsets: &[StoredOffset],
+    ) -> Result<()> {
         let mut key_to_set = HashSet::new();
 
         let mut redis = OptionFuture::from(self.redis_pool.as_ref().map(|f| f.aquire()))
@@ -50,6 +64,14 @@ impl PostgreSQLDumper {
         //};
         let mut postgres = self.postgres_pool.get().await?;
         let mut insert_tree = InsertTree::new();
+        // The statements run in any order, a provisional row must not come back once mined
+        let mined = results
+            .iter()
+            .filter_map(|result| match result {
+                EtlResult::MinedTransaction(m) => Some((m.chain_id, m.transaction_hash)),
+                _ => None,
+            })
+            .collect::<HashSet<_>>();
         for result in results {
             match result {
                 EtlResult::Contract(c) => {
@@ -64,12 +86,30 @@ impl PostgreSQLDumper {
                         insert_tree.insert(c);
                     }
                 }
+                EtlResult::Transaction(t)
+                    if t.provisional && mined.contains(&(t.chain_id, t.transaction_hash)) => {}
                 EtlResult::Transaction(t) => {
                     insert_tree.insert(t);
                 }
-                EtlResult::BlockWithChainId(b) => insert_tree.insert(b),
+                EtlResult::BlockWithChainId(b) => {
+                    insert_tree.insert(b);
+                    for w in b.withdrawals() {
+                        insert_tree.insert(&w);
+                    }
+                }
+                EtlResult::Log(l) => insert_tree.insert(l),
+                EtlResult::Blob(b) => insert_tree.insert(b),
+                EtlResult::MinedTransaction(m) => insert_tree.insert(m),
+                EtlResult::UnmatchedDetails(d) => insert_tree.insert(d),
+                EtlResult::UnmatchedLog(l) => insert_tree.insert(l),
             }
         }
+        for checkpoint in checkpoints {
+            insert_tree.insert(checkpoint);
+        }
+        for offset in offsets {
+            insert_tree.insert(offset);
+        }
 
         let transaction = postgres.transaction().await?;
         insert_tree.execute(&transaction).await?;
@@ -90,4 +130,165 @@ impl PostgreSQLDumper {
 
         Ok(())
     }
+
+    /// Insert a dead letter into `table`, an already quoted identifier
+    pub async fn insert_dead_letter(&self, table: &str, letter: &DeadLetter) -> Result<()> {
+        let postgres = self.postgres_pool.get().await?;
+        postgres
+            .execute(
+                &format!(
+                    "INSERT INTO {} (chain_id, topic, partition, \"offset\", error, payload) VALUES ($1, $2, $3, $4, $5, $6)",
+                    table
+                ),
+                &[
+                    &(letter.chain_id as i64),
+                    &letter.topic,
+                    &letter.partition,
+                    &letter.offset,
+                    &letter.error,
+                    &letter.payload,
+                ],
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Chains of the `chains` table, with `id`, `config` as the JSON of a `CHAINS` entry and
+    /// `status` columns
+    pub async fn load_chains(&self) -> Result<Vec<(Chain, ChainStatus)>> {
+        let postgres = self.postgres_pool.get().await?;
+        postgres
+            .query("SELECT config, status FROM chains ORDER BY id", &[])
+            .await?
+            .iter()
+            .map(|row| {
+                Ok((
+                    from_str(row.get::<_, &str>(0))?,
+                    ChainStatus::parse(row.get(1))?,
+                ))
+            })
+            .collect()
+    }
+
+    pub async fn save_chain(&self, chain: &Chain, status: ChainStatus) -> Result<()> {
+        let postgres = self.postgres_pool.get().await?;
+        postgres
+            .execute(
+                "INSERT INTO chains (id, config, status) VALUES ($1, $2, $3)
+                ON CONFLICT (id) DO UPDATE SET config = EXCLUDED.config, status = EXCLUDED.status",
+                &[
+                    &(chain.chain_id() as i64),
+                    &to_string(chain)?,
+                    &status.as_str(),
+                ],
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Checkpoints of a provider chain from the `checkpoints` table, with `chain_id`,
+    /// `block_number` and `block_hash` columns, newest first down to `retained` blocks before
+    /// the newest
+    pub async fn load_checkpoints(&self, chain_id: u64, retained: u64) -> Result<Vec<Checkpoint>> {
+        let postgres = self.postgres_pool.get().await?;
+        postgres
+            .query(
+                "SELECT block_number, block_hash FROM checkpoints WHERE chain_id = $1
+                AND block_number >= (SELECT max(block_number) FROM checkpoints WHERE chain_id = $1) - $2
+                ORDER BY block_number DESC",
+                &[&(chain_id as i64), &(retained as i64)],
+            )
+            .await?
+            .iter()
+            .map(|row| {
+                Ok(Checkpoint {
+                    chain_id,
+                    block_number: row.get::<_, i64>(0) as u64,
+                    block_hash: row.get::<_, &str>(1).parse()?,
+                    retained,
+                })
+            })
+            .collect()
+    }
+
+    /// Hashes of the provisional transactions of a chain, with the block they were expected in
+    pub async fn load_provisional(&self, chain_id: u64) -> Result<Vec<(H256, u64)>> {
+        let postgres = self.postgres_pool.get().await?;
+        postgres
+            .query(
+                "SELECT transaction_hash, block_number FROM transactions WHERE chain_id = $1 AND provisional",
+                &[&(chain_id as i64)],
+            )
+            .await?
+            .iter()
+            .map(|row| Ok((row.get::<_, &str>(0).parse()?, row.get::<_, i64>(1) as u64)))
+            .collect()
+    }
+
+    /// Offsets of a consumer group on a cluster from the `kafka_offsets` table, with
+    /// `group_id`, `cluster`, `topic`, `partition` and `offset` columns
+    pub async fn load_offsets(
+        &self,
+        group_id: &str,
+        cluster: &str,
+    ) -> Result<HashMap<(String, i32), i64>> {
+        let postgres = self.postgres_pool.get().await?;
+        Ok(postgres
+            .query(
+                "SELECT topic, partition, \"offset\" FROM kafka_offsets
+                WHERE group_id = $1 AND cluster = $2",
+                &[&group_id, &cluster],
+            )
+            .await?
+            .iter()
+            .map(|row| ((row.get(0), row.get(1)), row.get(2)))
+            .collect())
+    }
+
+    /// Start offset revisions applied to the partitions of a consumer group on a cluster, from
+    /// the `kafka_start_offsets` table with `group_id`, `cluster`, `topic`, `partition` and
+    /// `start` columns
+    pub async fn load_starts(
+        &self,
+        group_id: &str,
+        cluster: &str,
+    ) -> Result<HashMap<(String, i32), String>> {
+        let postgres = self.postgres_pool.get().await?;
+        Ok(postgres
+            .query(
+                "SELECT topic, partition, start FROM kafka_start_offsets
+                WHERE group_id = $1 AND cluster = $2",
+                &[&group_id, &cluster],
+            )
+            .await?
+            .iter()
+            .map(|row| ((row.get(0), row.get(1)), row.get(2)))
+            .collect())
+    }
+
+    /// Record the start offset revisions applied to partitions of a consumer group
+    pub async fn save_starts(
+        &self,
+        group_id: &str,
+        cluster: &str,
+        starts: &[AppliedStart],
+    ) -> Result<()> {
+        if starts.is_empty() {
+            return Ok(());
+        }
+        let mut postgres = self.postgres_pool.get().await?;
+        let transaction = postgres.transaction().await?;
+        for ((topic, partition), start) in starts {
+            transaction
+                .execute(
+                    "INSERT INTO kafka_start_offsets (group_id, cluster, topic, partition, start)
+                    VALUES ($1, $2, $3, $4, $5)
+                    ON CONFLICT (group_id, cluster, topic, partition) DO UPDATE SET start = EXCLUDED.start",
+                    &[&group_id, &cluster, topic, partition, start],
+                )
+                .await?;
+        }
+        transaction.commit().await?;
+        Ok(())
+    }
 }
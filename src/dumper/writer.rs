@@ -0,0 +1,205 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use log::{debug, info, warn};
+use tokio::{
+    select,
+    time::{sleep_until, Instant},
+};
+
+use crate::{
+    api::STATS,
+    channels::{ResultMessage, ResultReceiver, CHANNEL},
+    config::Dumper,
+    consumer::{Commiter, Watermarks},
+    types::{Checkpoint, EtlResult},
+};
+
+use super::POSTGRESQL_DUMPER;
+
+/// Batches waiting in the channel above which a flush warns
+const QUEUE_WARNING: usize = 100;
+
+/// Results received since the last flush, along with what they complete
+#[derive(Debug, Default)]
+struct Batch {
+    results: Vec<EtlResult>,
+    bytes: usize,
+    checkpoints: Vec<Checkpoint>,
+    watermarks: Watermarks,
+    /// When the first message of the batch was received
+    opened: Option<Instant>,
+}
+
+impl Batch {
+    fn add(&mut self, (results, commiter): ResultMessage) {
+        self.opened.get_or_insert_with(Instant::now);
+        self.bytes += results.iter().map(EtlResult::size).sum::<usize>();
+        self.results.extend(results);
+        match commiter {
+            Commiter::Kafka(commiter) => self.watermarks.track(commiter),
+            Commiter::Checkpoint(checkpoint) => self.checkpoints.push(checkpoint),
+            Commiter::None => {}
+        }
+    }
+
+    fn is_full(&self, policy: &Dumper) -> bool {
+        self.results.len() >= policy.max_batch_size || self.bytes >= policy.max_batch_bytes
+    }
+
+    /// When the batch must be flushed at the latest, `None` while it is empty
+    fn deadline(&self, policy: &Dumper) -> Option<Instant> {
+        self.opened
+            .map(|opened| opened + Duration::from_millis(policy.max_latency_ms))
+    }
+}
+
+/// Writes the results of the channel to Postgres in batches, flushed once they are full or
+/// their first result waited long enough. Kafka offsets are committed once their results
+/// are persisted.
+pub struct BatchWriter {
+    rx: ResultReceiver,
+    policy: &'static Dumper,
+    batch: Batch,
+}
+
+impl BatchWriter {
+    pub fn new(rx: ResultReceiver, policy: &'static Dumper) -> Self {
+        Self {
+            rx,
+            policy,
+            batch: Batch::default(),
+        }
+    }
+
+    /// Write until the channel is closed and drained
+    pub async fn run(mut self) -> Result<()> {
+        loop {
+            let deadline = self.batch.deadline(self.policy);
+            select! {
+                // Losing the race to the deadline must not drop a received batch
+                message = self.rx.recv_unrecorded() => {
+                    let Some(message) = message else {
+                        break;
+                    };
+                    self.rx.record(&message).await;
+                    self.batch.add(message);
+                    if self.batch.is_full(self.policy) {
+                        self.flush().await?;
+                    }
+                }
+                _ = sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => {
+                    self.flush().await?;
+                }
+            }
+        }
+
+        self.flush().await?;
+        info!("Dumper stopped");
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<()> {
+        let Batch {
+            results,
+            bytes,
+            checkpoints,
+            mut watermarks,
+            opened,
+        } = std::mem::take(&mut self.batch);
+        let Some(opened) = opened else {
+            return Ok(());
+        };
+
+        let started = Instant::now();
+        let offsets = watermarks.stored();
+        if !results.is_empty() || !checkpoints.is_empty() || !offsets.is_empty() {
+            POSTGRESQL_DUMPER
+                .insert_results(&results, &checkpoints, &offsets)
+                .await?;
+        }
+        // Everything received so far is persisted
+        watermarks.commit()?;
+
+        let queued = CHANNEL.len();
+        debug!(
+            "Dumped {} result traces to db in {:?}, {} to go",
+            results.len(),
+            started.elapsed(),
+            queued
+        );
+        if queued > QUEUE_WARNING {
+            warn!("Too many traces in queue: {}", queued);
+        }
+        STATS.increment("flushes", None).await;
+        STATS.set("flush_results", None, results.len() as u64).await;
+        STATS.set("flush_bytes", None, bytes as u64).await;
+        STATS
+            .set(
+                "flush_duration_ms",
+                None,
+                started.elapsed().as_millis() as u64,
+            )
+            .await;
+        // How long the oldest result of the batch waited to be persisted
+        STATS
+            .set(
+                "flush_latency_ms",
+                None,
+                opened.elapsed().as_millis() as u64,
+            )
+            .await;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::types::H256;
+
+    use crate::types::Blob;
+
+    use super::*;
+
+    #[test]
+    fn flush_on_size_bytes_or_latency() {
+        let policy = Dumper {
+            max_batch_size: 3,
+            max_batch_bytes: usize::MAX,
+            max_latency_ms: 500,
+        };
+        let blob = || -> EtlResult {
+            Blob {
+                chain_id: 1,
+                versioned_hash: H256::zero(),
+                transaction_hash: H256::zero(),
+                block_number: 1,
+                index: 0,
+            }
+            .into()
+        };
+
+        let mut batch = Batch::default();
+        assert_eq!(batch.deadline(&policy), None);
+
+        // Messages without results still open the batch, their offsets must be committed
+        batch.add((vec![], Commiter::None));
+        let deadline = batch.deadline(&policy).unwrap();
+        batch.add((vec![blob(), blob()], Commiter::None));
+        assert_eq!(batch.deadline(&policy), Some(deadline));
+        assert!(!batch.is_full(&policy));
+        batch.add((vec![blob()], Commiter::None));
+        assert!(batch.is_full(&policy));
+
+        let policy = Dumper {
+            max_batch_size: usize::MAX,
+            max_batch_bytes: blob().size() * 2,
+            max_latency_ms: 500,
+        };
+        let mut batch = Batch::default();
+        batch.add((vec![blob()], Commiter::None));
+        assert!(!batch.is_full(&policy));
+        batch.add((vec![blob()], Commiter::None));
+        assert!(batch.is_full(&policy));
+    }
+}
@@ -5,5 +5,7 @@ pub mod constants;
 pub mod consumer;
 pub mod dumper;
 pub mod providers;
+pub mod registry;
+pub mod shutdown;
 pub mod types;
 pub mod utils;
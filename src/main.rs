@@ -1,21 +1,22 @@
 use std::{
-    collections::HashMap,
     net::Ipv4Addr,
     panic::{set_hook, take_hook},
     process::exit,
+    time::Duration,
 };
 
 use anyhow::{anyhow, Error, Result};
 use axum::{routing::get, serve, Router};
 use log::{error, info};
-use tokio::{net::TcpListener, select, spawn};
+use tokio::{net::TcpListener, select, spawn, task::JoinHandle, time::timeout};
 use tracing::level_filters::LevelFilter;
 use tracing_subscriber::EnvFilter;
 use zkscan_etl::{
-    api::{self, STATS},
+    api,
     channels::CHANNEL,
     config::CONFIG,
-    consumer::{BlockConsumer, TraceConsumer, WebSocketConsumer},
+    consumer::{ChainSupervisor, SUPERVISOR},
+    shutdown::{Shutdown, SHUTDOWN},
 };
 
 #[tokio::main]
@@ -46,119 +47,44 @@ async fn main() -> Result<(), Error> {
         .compact()
         .init();
 
-    let handle_log = spawn(async move {
-        let mut cnt = HashMap::<u64, u64>::new();
-        let mut rx = CHANNEL.result_tx.subscribe();
-
-        while let Ok((traces, _)) = rx.recv().await {
-            for t in traces {
-                let v = cnt.entry(t.chain_id()).and_modify(|e| *e += 1).or_insert(1);
-                if *v % 10000 == 0 {
-                    info!("Received {} result traces from chain {}", v, t.chain_id());
-                }
-
-                #[cfg(feature = "trace-result")]
-                {
-                    use zkscan_etl::types::EtlResult;
-                    match &t {
-                        EtlResult::BlockWithChainId(b) => {
-                            info!(
-                                "Received block {} from chain {}",
-                                b.block.number,
-                                t.chain_id()
-                            );
-                        }
-                        _ => {
-                            info!("Received result from chain {}: {}", t.chain_id(), t);
-                        }
-                    }
-                }
-            }
-        }
-        Result::<()>::Ok(())
-    });
-
+    // Results are still received without dumping, senders would wait forever otherwise
     #[cfg(feature = "no-dump")]
-    let handle_dump = spawn(async move {
-        use futures_util::future::pending;
-        pending::<()>().await;
+    let mut handle_dump = spawn(async move {
+        let mut rx = CHANNEL.receiver()?;
+        while rx.recv().await.is_some() {}
         Result::<()>::Ok(())
     });
     #[cfg(not(feature = "no-dump"))]
-    let handle_dump = spawn(async move {
-        use log::{debug, warn};
-        use std::time::Duration;
-        use tokio::time::Instant;
-        use zkscan_etl::{
-            consumer::{Commiter, TopicCommiter},
-            dumper::POSTGRESQL_DUMPER,
-        };
-
-        let mut rx = CHANNEL.result_tx.subscribe();
-
-        let mut buffer = vec![];
-        let mut latest_partition: Option<TopicCommiter> = None;
-        let mut last_commit = Instant::now();
-        while let Ok((t, commiter)) = rx.recv().await {
-            buffer.extend(t);
-
-            match (!rx.is_empty(), buffer.len() > 100_000) {
-                (true, false) => continue,
-                _ => {
-                    let buffer_len = buffer.len();
-                    POSTGRESQL_DUMPER.insert_results(&buffer).await?;
-                    buffer.clear();
-
-                    let current_rx_len = rx.len();
-                    debug!(
-                        "Dumped {} result traces to db, {} to go",
-                        buffer_len, current_rx_len
-                    );
-                    if current_rx_len > 100 {
-                        warn!("Too many traces in queue: {}", current_rx_len);
-                    }
-                }
-            }
+    let mut handle_dump = spawn(async move {
+        use zkscan_etl::dumper::BatchWriter;
 
-            if let Commiter::Kafka(partition) = commiter {
-                let now = Instant::now();
-                match latest_partition {
-                    Some(l)
-                        if l.topic_id != partition.topic_id
-                            && partition.offset > l.offset + 100
-                            && last_commit.duration_since(now) > Duration::from_secs(1) =>
-                    {
-                        l.commit()?;
-                        last_commit = now;
-                    }
-                    _ => {}
-                }
-                latest_partition = Some(partition);
-            }
-        }
-
-        Result::<()>::Ok(())
+        BatchWriter::new(CHANNEL.receiver()?, &CONFIG.dumper)
+            .run()
+            .await
     });
 
-    let server = spawn(async move {
+    let mut server = spawn(async move {
         let app = Router::new()
             .route("/", get(|| async { "Ok" }))
             .nest("/", api::routes());
         let listener = TcpListener::bind((Ipv4Addr::UNSPECIFIED, CONFIG.port)).await?;
         info!("Server is listening on http://0.0.0.0:{}", CONFIG.port,);
         serve(listener, app)
+            .with_graceful_shutdown(SHUTDOWN.wait())
             .await
             .map_err(|e| anyhow!("Server error: {}", e))
     });
 
     match select! {
-        e = TraceConsumer::poll() => e,
-        e = BlockConsumer::poll() => e,
-        e = WebSocketConsumer::poll() => e,
-        e = STATS.watch() => e,
-        e = handle_log => e,
-        e = handle_dump => e,
-        e = server => e,
+        e = ChainSupervisor::poll() => e,
+        e = &mut handle_dump => e,
+        e = &mut server => e,
+        // Only file chains were configured and they are all ingested
+        _ = SUPERVISOR.finished() => return shutdown(handle_dump, server).await,
+        e = Shutdown::signal() => match e {
+            Ok(()) => return shutdown(handle_dump, server).await,
+            Err(e) => Ok(Err(e)),
+        },
     } {
         Ok(Err(e)) => error!("Error: {}", e),
         Err(e) => error!("Join Error: {}", e),
@@ -167,3 +93,33 @@ async fn main() -> Result<(), Error> {
 
     Ok(())
 }
+
+/// Stop the consumers, dump and commit everything they sent, then stop the server, giving
+/// up after `SHUTDOWN_TIMEOUT`
+async fn shutdown(
+    handle_dump: JoinHandle<Result<()>>,
+    server: JoinHandle<Result<()>>,
+) -> Result<()> {
+    info!("Shutting down, waiting up to {}s", CONFIG.shutdown_timeout);
+    SHUTDOWN.trigger();
+    let drain = async {
+        SUPERVISOR.stop().await;
+        // Consumers are stopped, the dumper ends once it persisted what they sent
+        CHANNEL.close();
+        handle_dump.await??;
+        server.await??;
+        Result::<()>::Ok(())
+    };
+    match timeout(Duration::from_secs(CONFIG.shutdown_timeout), drain).await {
+        Ok(result) => {
+            result?;
+            info!("Shut down gracefully");
+            Ok(())
+        }
+        Err(_) => Err(anyhow!(
+            "Shutdown timed out after {}s, {} result batches left",
+            CONFIG.shutdown_timeout,
+            CHANNEL.len()
+        )),
+    }
+}
@@ -1,17 +1,22 @@
 use std::{collections::HashMap, sync::Arc};
 
 use anyhow::{anyhow, Result};
-use ethers::providers::{Http, Provider, Ws};
+use ethers::providers::{Provider, Ws};
 use once_cell::sync::Lazy;
 use tokio::sync::RwLock;
 
-use crate::config::{Chain, CONFIG};
+use crate::{config::Chain, registry::CHAINS};
+
+mod failover;
+pub use failover::*;
 
 pub static PROVIDER_POOL: Lazy<ProviderPool> = Lazy::new(ProviderPool::new);
 
+pub type RpcProvider = Provider<FailoverClient>;
+
 #[derive(Debug, Default)]
 pub struct ProviderPool {
-    rpc: RwLock<HashMap<u64, Arc<Provider<Http>>>>,
+    rpc: RwLock<HashMap<u64, Arc<RpcProvider>>>,
     ws: RwLock<HashMap<u64, Arc<Provider<Ws>>>>,
 }
 
@@ -23,20 +28,17 @@ impl ProviderPool {
         }
     }
 
-    pub async fn get_rpc(&self, chain_id: u64) -> Result<Arc<Provider<Http>>> {
+    pub async fn get_rpc(&self, chain_id: u64) -> Result<Arc<RpcProvider>> {
         let mut rpc = self.rpc.write().await;
         if let Some(provider) = rpc.get(&chain_id) {
             return Ok(provider.clone());
         }
-        let rpc_url = CONFIG
-            .chains
-            .iter()
-            .find_map(|c| match c {
-                Chain::Provider(chain) => (chain.id == chain_id).then_some(chain.rpc_url.clone()),
-                _ => None,
-            })
-            .ok_or_else(|| anyhow!("No RPC provider for chain {}", chain_id))?;
-        let provider = Arc::new(Provider::<Http>::try_from(rpc_url)?);
+        let endpoints = match CHAINS.get(chain_id) {
+            Some(Chain::Provider(chain)) => Some(chain.rpc_url.endpoints()),
+            _ => None,
+        }
+        .ok_or_else(|| anyhow!("No RPC provider for chain {}", chain_id))?;
+        let provider = Arc::new(Provider::new(FailoverClient::new(endpoints)?));
         rpc.insert(chain_id, provider.clone());
         Ok(provider)
     }
@@ -46,16 +48,23 @@ impl ProviderPool {
         if let Some(provider) = ws.get(&chain_id) {
             return Ok(provider.clone());
         }
-        let ws_url = CONFIG
-            .chains
-            .iter()
-            .find_map(|c| match c {
-                Chain::Provider(chain) => (chain.id == chain_id).then_some(chain.ws_url.clone()),
-                _ => None,
-            })
-            .ok_or_else(|| anyhow!("No WS provider for chain {}", chain_id))?;
+        let ws_url = match CHAINS.get(chain_id) {
+            Some(Chain::Provider(chain)) => Some(chain.ws_url.clone()),
+            _ => None,
+        }
+        .ok_or_else(|| anyhow!("No WS provider for chain {}", chain_id))?;
         let provider = Arc::new(Provider::<Ws>::connect(ws_url).await?);
         ws.insert(chain_id, provider.clone());
         Ok(provider)
     }
+
+    /// Drop the cached WS provider so the next `get_ws` opens a fresh connection
+    pub async fn evict_ws(&self, chain_id: u64) {
+        self.ws.write().await.remove(&chain_id);
+    }
+
+    /// Drop the cached RPC provider so the next `get_rpc` reads the chain's endpoints again
+    pub async fn evict_rpc(&self, chain_id: u64) {
+        self.rpc.write().await.remove(&chain_id);
+    }
 }
@@ -0,0 +1,284 @@
+use std::{
+    fmt::Debug,
+    str::FromStr,
+    sync::{Arc, Mutex, Weak},
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use ethers::{
+    providers::{Http, HttpClientError, JsonRpcClient, JsonRpcError},
+    types::U64,
+};
+use futures_util::future::join_all;
+use log::{debug, warn};
+use reqwest::{Client, Url};
+use serde::{de::DeserializeOwned, Serialize};
+use tokio::{
+    spawn,
+    time::{sleep, timeout},
+};
+
+use crate::config::RpcEndpoint;
+
+/// How far behind the best known head an endpoint may be before it is considered lagging
+const MAX_HEAD_LAG: u64 = 5;
+const HEAD_REFRESH_INTERVAL: Duration = Duration::from_secs(10);
+/// A hung endpoint fails after these, so requests move on to the next one
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+/// Head refreshes are cheap, an endpoint slower than this is lagging anyway
+const HEAD_TIMEOUT: Duration = Duration::from_secs(5);
+/// Smoothing factor of the latency and error rate moving averages
+const EWMA_ALPHA: f64 = 0.2;
+/// JSON-RPC error codes of rate limited requests, the HTTP status some providers use and the
+/// EIP-1474 "limit exceeded" code
+const RATE_LIMIT_CODES: [i64; 2] = [429, -32005];
+
+#[derive(Debug, Default)]
+struct Health {
+    /// Exponentially weighted request latency in milliseconds
+    latency_ms: f64,
+    /// Exponentially weighted ratio of failed requests, 0.0 to 1.0
+    error_rate: f64,
+    head: u64,
+    /// Smooth weighted round robin accumulator
+    current_weight: i64,
+}
+
+#[derive(Debug)]
+struct Endpoint {
+    url: String,
+    weight: u32,
+    client: Http,
+    health: Mutex<Health>,
+}
+
+impl Endpoint {
+    fn record(&self, elapsed: Duration, ok: bool) {
+        let mut health = self.health.lock().expect("poisoned health lock");
+        let latency = elapsed.as_secs_f64() * 1000.0;
+        health.latency_ms = match health.latency_ms == 0.0 {
+            true => latency,
+            false => health.latency_ms * (1.0 - EWMA_ALPHA) + latency * EWMA_ALPHA,
+        };
+        health.error_rate =
+            health.error_rate * (1.0 - EWMA_ALPHA) + if ok { 0.0 } else { EWMA_ALPHA };
+    }
+
+    /// Configured weight scaled down by error rate, latency and head lag, zero when the
+    /// endpoint should only be used as a last resort
+    fn effective_weight(&self, best_head: u64) -> i64 {
+        let health = self.health.lock().expect("poisoned health lock");
+        if best_head > health.head + MAX_HEAD_LAG {
+            return 0;
+        }
+        let success = (1.0 - health.error_rate).max(0.0);
+        let speed = 100.0 / (health.latency_ms + 100.0);
+        (self.weight as f64 * 1000.0 * success * speed) as i64
+    }
+}
+
+/// JSON-RPC client balancing requests over several HTTP endpoints by weight and health,
+/// failing over to the next best endpoint whenever one cannot answer a request
+#[derive(Debug, Clone)]
+pub struct FailoverClient {
+    endpoints: Arc<Vec<Endpoint>>,
+}
+
+impl FailoverClient {
+    pub fn new(endpoints: Vec<RpcEndpoint>) -> Result<Self> {
+        if endpoints.is_empty() {
+            bail!("At least one RPC endpoint is required");
+        }
+        let http = Client::builder()
+            .connect_timeout(CONNECT_TIMEOUT)
+            .timeout(REQUEST_TIMEOUT)
+            .build()?;
+        let endpoints = endpoints
+            .into_iter()
+            .map(|RpcEndpoint { url, weight }| {
+                Ok(Endpoint {
+                    client: Http::new_with_client(Url::from_str(&url)?, http.clone()),
+                    url,
+                    weight,
+                    health: Mutex::new(Health::default()),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let client = Self {
+            endpoints: Arc::new(endpoints),
+        };
+
+        // Head tracking is only useful to pick between several endpoints
+        if client.endpoints.len() > 1 {
+            Self::track_heads(Arc::downgrade(&client.endpoints));
+        }
+        Ok(client)
+    }
+
+    /// Periodically refresh the head height of every endpoint until the client is dropped,
+    /// all at once so a stalled endpoint doesn't hold the others back
+    fn track_heads(endpoints: Weak<Vec<Endpoint>>) {
+        spawn(async move {
+            while let Some(endpoints) = endpoints.upgrade() {
+                join_all(endpoints.iter().map(Self::refresh_head)).await;
+                drop(endpoints);
+                sleep(HEAD_REFRESH_INTERVAL).await;
+            }
+        });
+    }
+
+    async fn refresh_head(endpoint: &Endpoint) {
+        let start = Instant::now();
+        let head = timeout(
+            HEAD_TIMEOUT,
+            endpoint.client.request::<_, U64>("eth_blockNumber", ()),
+        )
+        .await;
+        endpoint.record(start.elapsed(), matches!(head, Ok(Ok(_))));
+        match head {
+            Ok(Ok(head)) => {
+                endpoint.health.lock().expect("poisoned health lock").head = head.as_u64()
+            }
+            Ok(Err(e)) => warn!("Failed to refresh head of {}: {}", endpoint.url, e),
+            Err(_) => warn!(
+                "Failed to refresh head of {}: no answer within {:?}",
+                endpoint.url, HEAD_TIMEOUT
+            ),
+        }
+    }
+
+    /// Endpoint indices in the order they should be tried: one picked by smooth weighted
+    /// round robin over the healthy endpoints, then the rest by effective weight
+    fn order(&self) -> Vec<usize> {
+        let best_head = self
+            .endpoints
+            .iter()
+            .map(|e| e.health.lock().expect("poisoned health lock").head)
+            .max()
+            .unwrap_or_default();
+        let weights = self
+            .endpoints
+            .iter()
+            .map(|e| e.effective_weight(best_head))
+            .collect::<Vec<_>>();
+
+        let mut order = (0..self.endpoints.len()).collect::<Vec<_>>();
+        order.sort_by_key(|i| -weights[*i]);
+
+        let total: i64 = weights.iter().sum();
+        if total > 0 {
+            let mut picked = None;
+            for (i, endpoint) in self.endpoints.iter().enumerate() {
+                let mut health = endpoint.health.lock().expect("poisoned health lock");
+                health.current_weight += weights[i];
+                if picked.is_none_or(|(_, w)| health.current_weight > w) {
+                    picked = Some((i, health.current_weight));
+                }
+            }
+            if let Some((i, _)) = picked {
+                self.endpoints[i]
+                    .health
+                    .lock()
+                    .expect("poisoned health lock")
+                    .current_weight -= total;
+                order.retain(|e| *e != i);
+                order.insert(0, i);
+            }
+        }
+        order
+    }
+
+    /// Whether another endpoint may answer the request: transport errors, timeouts, rate
+    /// limits and responses that are not JSON-RPC such as gateway error pages. Any other
+    /// JSON-RPC error, like a revert or invalid params, is the answer to the request itself.
+    fn should_fail_over(error: &HttpClientError) -> bool {
+        match error {
+            HttpClientError::ReqwestError(_) | HttpClientError::SerdeJson { .. } => true,
+            HttpClientError::JsonRpcError(JsonRpcError { code, .. }) => {
+                RATE_LIMIT_CODES.contains(code)
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl JsonRpcClient for FailoverClient {
+    type Error = HttpClientError;
+
+    async fn request<T, R>(&self, method: &str, params: T) -> Result<R, Self::Error>
+    where
+        T: Debug + Serialize + Send + Sync,
+        R: DeserializeOwned + Send,
+    {
+        let mut last_error = None;
+        for i in self.order() {
+            let endpoint = &self.endpoints[i];
+            let start = Instant::now();
+            let result = endpoint.client.request(method, &params).await;
+            let failed = matches!(&result, Err(e) if Self::should_fail_over(e));
+            endpoint.record(start.elapsed(), !failed);
+            match result {
+                Ok(r) => return Ok(r),
+                Err(e) if !failed => return Err(e),
+                Err(e) => {
+                    debug!("Request {} to {} failed: {}", method, endpoint.url, e);
+                    last_error = Some(e);
+                }
+            }
+        }
+        Err(last_error.expect("at least one endpoint is configured"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn prefers_heavier_and_healthier_endpoints() {
+        let client = FailoverClient::new(vec![
+            RpcEndpoint {
+                url: "http://a:8545".to_string(),
+                weight: 3,
+            },
+            RpcEndpoint {
+                url: "http://b:8545".to_string(),
+                weight: 1,
+            },
+        ])
+        .expect("failed to create client");
+
+        let firsts = (0..4).map(|_| client.order()[0]).collect::<Vec<_>>();
+        assert_eq!(firsts.iter().filter(|i| **i == 0).count(), 3);
+        assert_eq!(firsts.iter().filter(|i| **i == 1).count(), 1);
+
+        // A lagging endpoint is only kept as a last resort
+        client.endpoints[0].health.lock().unwrap().head = 100;
+        client.endpoints[1].health.lock().unwrap().head = 90;
+        assert!((0..4).all(|_| client.order() == vec![0, 1]));
+    }
+
+    #[test]
+    fn fails_over_only_when_another_endpoint_may_answer() {
+        let rpc_error = |code| {
+            HttpClientError::JsonRpcError(JsonRpcError {
+                code,
+                message: String::new(),
+                data: None,
+            })
+        };
+        assert!(!FailoverClient::should_fail_over(&rpc_error(3)));
+        assert!(!FailoverClient::should_fail_over(&rpc_error(-32602)));
+        assert!(FailoverClient::should_fail_over(&rpc_error(-32005)));
+        assert!(FailoverClient::should_fail_over(&rpc_error(429)));
+
+        let gateway_error = HttpClientError::SerdeJson {
+            err: serde_json::from_str::<U64>("<html>").unwrap_err(),
+            text: "<html>502 Bad Gateway</html>".to_string(),
+        };
+        assert!(FailoverClient::should_fail_over(&gateway_error));
+    }
+}
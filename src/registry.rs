@@ -0,0 +1,200 @@
+use std::{collections::BTreeMap, fmt::Display, sync::RwLock};
+
+use anyhow::{anyhow, bail, Error, Result};
+use log::info;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{Chain, KafkaChainConfig, CONFIG},
+    consumer::ClusterConsumer,
+    dumper::POSTGRESQL_DUMPER,
+};
+
+/// Chains to consume, those of `CHAINS` along with the ones added, paused or removed at
+/// runtime through the admin API
+pub static CHAINS: Lazy<ChainRegistry> = Lazy::new(ChainRegistry::new);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChainStatus {
+    Running,
+    Paused,
+    /// Kept so a chain of `CHAINS` is not added back on restart
+    Removed,
+}
+
+#[derive(Debug)]
+pub enum RegistryError {
+    NotFound(u64),
+    Exists(u64),
+    Invalid(Error),
+    Storage(Error),
+}
+
+/// Registered chains by id. Chains added at runtime are leaked so consumers can hold them
+/// for the whole process like the ones of `CONFIG`, they are few and small.
+#[derive(Debug, Default)]
+pub struct ChainRegistry(RwLock<BTreeMap<u64, (&'static Chain, ChainStatus)>>);
+
+impl ChainRegistry {
+    pub fn new() -> Self {
+        Self(RwLock::new(
+            CONFIG
+                .chains
+                .iter()
+                .map(|c| (c.chain_id(), (c, ChainStatus::Running)))
+                .collect(),
+        ))
+    }
+
+    /// Whether chain changes are persisted, only when the admin API is enabled
+    fn persisted() -> bool {
+        CONFIG.admin_token.is_some()
+    }
+
+    /// Apply the chains stored in the `chains` table, which take precedence over `CHAINS`
+    pub async fn load(&self) -> Result<()> {
+        if !Self::persisted() {
+            return Ok(());
+        }
+        let stored = POSTGRESQL_DUMPER.load_chains().await?;
+        info!("Loaded {} stored chains", stored.len());
+        let mut chains = self.0.write().expect("poisoned registry lock");
+        for (chain, status) in stored {
+            let chain: &'static Chain = Box::leak(Box::new(chain));
+            chains.insert(chain.chain_id(), (chain, status));
+        }
+        Ok(())
+    }
+
+    /// Config of a chain that was not removed
+    pub fn get(&self, chain_id: u64) -> Option<&'static Chain> {
+        self.list()
+            .into_iter()
+            .find_map(|(c, _)| (c.chain_id() == chain_id).then_some(c))
+    }
+
+    /// Every chain that was not removed, by id
+    pub fn list(&self) -> Vec<(&'static Chain, ChainStatus)> {
+        self.0
+            .read()
+            .expect("poisoned registry lock")
+            .values()
+            .filter(|(_, status)| *status != ChainStatus::Removed)
+            .copied()
+            .collect()
+    }
+
+    /// Chains to consume, by id
+    pub fn running(&self) -> Vec<&'static Chain> {
+        self.list()
+            .into_iter()
+            .filter_map(|(c, status)| (status == ChainStatus::Running).then_some(c))
+            .collect()
+    }
+
+    pub async fn add(&self, chain: Chain) -> Result<(), RegistryError> {
+        let chain_id = chain.chain_id();
+        if self.get(chain_id).is_some() {
+            return Err(RegistryError::Exists(chain_id));
+        }
+        self.validate(&chain).map_err(RegistryError::Invalid)?;
+        self.store(Box::leak(Box::new(chain)), ChainStatus::Running)
+            .await
+    }
+
+    pub async fn set_status(
+        &self,
+        chain_id: u64,
+        status: ChainStatus,
+    ) -> Result<(), RegistryError> {
+        let chain = self
+            .get(chain_id)
+            .ok_or(RegistryError::NotFound(chain_id))?;
+        if status == ChainStatus::Running {
+            self.validate(chain).map_err(RegistryError::Invalid)?;
+        }
+        self.store(chain, status).await
+    }
+
+    async fn store(&self, chain: &'static Chain, status: ChainStatus) -> Result<(), RegistryError> {
+        if Self::persisted() {
+            POSTGRESQL_DUMPER
+                .save_chain(chain, status)
+                .await
+                .map_err(RegistryError::Storage)?;
+        }
+        info!("Chain {} is now {}", chain.chain_id(), status);
+        self.0
+            .write()
+            .expect("poisoned registry lock")
+            .insert(chain.chain_id(), (chain, status));
+        Ok(())
+    }
+
+    /// Check that a chain can start along with the running ones, a Kafka chain needs a
+    /// configured cluster and topics not used by another chain of that cluster
+    fn validate(&self, chain: &Chain) -> Result<()> {
+        let Chain::Kafka(chain) = chain else {
+            return Ok(());
+        };
+        let cluster = chain.options.cluster.as_deref();
+        if CONFIG.kafka_config(cluster).is_none() {
+            bail!(
+                "Kafka cluster {} is not configured",
+                cluster.unwrap_or("default")
+            );
+        }
+        let mut chains = self
+            .running()
+            .into_iter()
+            .filter_map(|c| match c {
+                Chain::Kafka(c) if c.id != chain.id && c.options.cluster.as_deref() == cluster => {
+                    Some(c)
+                }
+                _ => None,
+            })
+            .collect::<Vec<&KafkaChainConfig>>();
+        chains.push(chain);
+        ClusterConsumer::validate(&chains)
+    }
+}
+
+impl ChainStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChainStatus::Running => "running",
+            ChainStatus::Paused => "paused",
+            ChainStatus::Removed => "removed",
+        }
+    }
+
+    pub fn parse(status: &str) -> Result<Self> {
+        match status {
+            "running" => Ok(ChainStatus::Running),
+            "paused" => Ok(ChainStatus::Paused),
+            "removed" => Ok(ChainStatus::Removed),
+            _ => Err(anyhow!("Unknown chain status {}", status)),
+        }
+    }
+}
+
+impl Display for ChainStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl Display for RegistryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RegistryError::NotFound(id) => write!(f, "Chain {} is not registered", id),
+            RegistryError::Exists(id) => write!(f, "Chain {} is already registered", id),
+            RegistryError::Invalid(e) => write!(f, "Invalid chain: {}", e),
+            RegistryError::Storage(e) => write!(f, "Failed to store chain: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for RegistryError {}
@@ -0,0 +1,86 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use once_cell::sync::Lazy;
+use tokio::{
+    select,
+    signal::{
+        ctrl_c,
+        unix::{signal, SignalKind},
+    },
+    sync::watch::{channel, Receiver, Sender},
+    time::sleep,
+};
+
+/// Set once the process is asked to stop, consumers then finish what they are processing
+/// and stop reading
+pub static SHUTDOWN: Lazy<Shutdown> = Lazy::new(Shutdown::new);
+
+#[derive(Debug)]
+pub struct Shutdown {
+    tx: Sender<bool>,
+    /// Kept so triggering never fails for lack of receivers
+    _rx: Receiver<bool>,
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        let (tx, _rx) = channel(false);
+        Self { tx, _rx }
+    }
+
+    pub fn trigger(&self) {
+        self.tx.send_replace(true);
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        *self.tx.borrow()
+    }
+
+    /// Resolve once shutdown is triggered
+    pub async fn wait(&self) {
+        let mut rx = self.tx.subscribe();
+        // Only fails once the sender is dropped, which `SHUTDOWN` never is
+        rx.wait_for(|triggered| *triggered).await.ok();
+    }
+
+    /// Sleep unless shutdown is triggered first, returns whether the whole delay elapsed
+    pub async fn sleep(&self, duration: Duration) -> bool {
+        select! {
+            _ = sleep(duration) => true,
+            _ = self.wait() => false,
+        }
+    }
+
+    /// Resolve on SIGTERM or Ctrl-C
+    pub async fn signal() -> Result<()> {
+        let mut terminate = signal(SignalKind::terminate())?;
+        select! {
+            _ = terminate.recv() => Ok(()),
+            result = ctrl_c() => Ok(result?),
+        }
+    }
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn sleep_interrupted_by_trigger() {
+        let shutdown = Shutdown::new();
+        assert!(shutdown.sleep(Duration::from_millis(1)).await);
+        assert!(!shutdown.is_triggered());
+
+        shutdown.trigger();
+        assert!(shutdown.is_triggered());
+        assert!(!shutdown.sleep(Duration::from_secs(60)).await);
+        shutdown.wait().await;
+    }
+}
@@ -1,24 +1,94 @@
 use std::fmt::Display;
 
-use ethers::types::{Address, Block as EtherBlock, H256, H64};
+use ethers::types::{
+    Address, Block as EtherBlock, Bloom, Bytes, Withdrawal as EtherWithdrawal, H256, H64, U256, U64,
+};
 use serde::{Deserialize, Serialize};
 use serde_json::to_string_pretty;
 
+use super::{u256_as_string, u256_from_string, value_as_string, value_from_string};
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Block {
     pub number: u64,
     pub timestamp: u64,
     pub hash: H256,
     pub parent_hash: H256,
-    pub transaction_count: u32,
-    pub nonce: H64,
+    /// Unset along with the nonce, difficulty and size in datasets that don't export them
+    #[serde(default)]
+    pub transaction_count: Option<u32>,
+    #[serde(default)]
+    pub nonce: Option<H64>,
     pub miner: Address,
-    pub difficulty: u64,
-    pub total_difficulty: f64,
-    pub size: u32,
+    #[serde(
+        default,
+        deserialize_with = "value_from_string",
+        serialize_with = "value_as_string"
+    )]
+    pub difficulty: Option<U256>,
+    /// Unset by nodes that dropped it after the merge
+    #[serde(
+        default,
+        deserialize_with = "value_from_string",
+        serialize_with = "value_as_string"
+    )]
+    pub total_difficulty: Option<U256>,
+    #[serde(default)]
+    pub size: Option<u32>,
     pub gas_limit: u64,
     pub gas_used: u64,
-    pub base_fee_per_gas: u64,
+    /// Unset before London
+    #[serde(
+        default,
+        deserialize_with = "value_from_string",
+        serialize_with = "value_as_string"
+    )]
+    pub base_fee_per_gas: Option<U256>,
+    /// L1 block the block was sequenced at, Arbitrum only
+    #[serde(default)]
+    pub l1_block_number: Option<u64>,
+    /// EIP-4844 fields, unset before Cancun
+    #[serde(default)]
+    pub blob_gas_used: Option<u64>,
+    #[serde(default)]
+    pub excess_blob_gas: Option<u64>,
+    #[serde(default)]
+    pub parent_beacon_block_root: Option<H256>,
+    /// Header fields, unset when the source doesn't provide them
+    #[serde(default, alias = "sha3_uncles")]
+    pub uncles_hash: Option<H256>,
+    #[serde(default)]
+    pub state_root: Option<H256>,
+    #[serde(default)]
+    pub transactions_root: Option<H256>,
+    #[serde(default)]
+    pub receipts_root: Option<H256>,
+    #[serde(default)]
+    pub logs_bloom: Option<Bloom>,
+    #[serde(default)]
+    pub extra_data: Option<Bytes>,
+    #[serde(default)]
+    pub mix_hash: Option<H256>,
+    /// Set from Shanghai
+    #[serde(default)]
+    pub withdrawals_root: Option<H256>,
+    #[serde(default)]
+    pub withdrawals: Vec<Withdrawal>,
+}
+
+/// Validator withdrawal processed in a block
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Withdrawal {
+    pub index: u64,
+    #[serde(alias = "validatorIndex")]
+    pub validator_index: u64,
+    pub address: Address,
+    /// In gwei
+    #[serde(
+        deserialize_with = "u256_from_string",
+        serialize_with = "u256_as_string"
+    )]
+    pub amount: U256,
 }
 
 impl Display for Block {
@@ -54,23 +124,155 @@ impl Block {
                 size,
                 nonce,
                 base_fee_per_gas,
+                blob_gas_used,
+                excess_blob_gas,
+                parent_beacon_block_root,
+                uncles_hash,
+                state_root,
+                transactions_root,
+                receipts_root,
+                logs_bloom,
+                extra_data,
+                mix_hash,
+                withdrawals_root,
+                withdrawals,
+                other,
                 ..
             } => Some(Block {
                 number: number.as_u64(),
                 timestamp: timestamp.as_u64(),
                 hash,
                 parent_hash,
-                transaction_count: transactions.len() as u32,
-                nonce: nonce.unwrap_or_default(),
+                transaction_count: Some(transactions.len() as u32),
+                nonce,
                 miner: author.unwrap_or_default(),
-                difficulty: difficulty.as_u64(),
-                total_difficulty: total_difficulty.unwrap_or_default().as_u64() as f64,
-                size: size.unwrap_or_default().as_u32(),
+                difficulty: Some(difficulty),
+                total_difficulty,
+                size: size.map(|s| s.as_u32()),
                 gas_limit: gas_limit.as_u64(),
                 gas_used: gas_used.as_u64(),
-                base_fee_per_gas: base_fee_per_gas.unwrap_or_default().as_u64(),
+                base_fee_per_gas,
+                l1_block_number: other
+                    .get_deserialized::<U64>("l1BlockNumber")
+                    .and_then(Result::ok)
+                    .map(|n| n.as_u64()),
+                blob_gas_used: blob_gas_used.map(|g| g.as_u64()),
+                excess_blob_gas: excess_blob_gas.map(|g| g.as_u64()),
+                parent_beacon_block_root,
+                uncles_hash: Some(uncles_hash),
+                state_root: Some(state_root),
+                transactions_root: Some(transactions_root),
+                receipts_root: Some(receipts_root),
+                logs_bloom,
+                extra_data: Some(extra_data),
+                mix_hash,
+                withdrawals_root,
+                withdrawals: withdrawals
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(Withdrawal::from)
+                    .collect(),
             }),
             _ => None,
         }
     }
 }
+
+impl From<EtherWithdrawal> for Withdrawal {
+    fn from(withdrawal: EtherWithdrawal) -> Self {
+        Self {
+            index: withdrawal.index.as_u64(),
+            validator_index: withdrawal.validator_index.as_u64(),
+            address: withdrawal.address,
+            amount: withdrawal.amount,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{from_value, json};
+
+    use super::*;
+
+    #[test]
+    fn lossless_block_fields() {
+        let block: EtherBlock<H256> = from_value(json!({
+            "hash": format!("{:?}", H256::repeat_byte(1)),
+            "parentHash": format!("{:?}", H256::repeat_byte(2)),
+            "sha3Uncles": format!("{:?}", H256::repeat_byte(3)),
+            "miner": format!("{:?}", Address::repeat_byte(4)),
+            "stateRoot": format!("{:?}", H256::repeat_byte(5)),
+            "transactionsRoot": format!("{:?}", H256::repeat_byte(6)),
+            "receiptsRoot": format!("{:?}", H256::repeat_byte(7)),
+            "number": "0xf4240",
+            "gasUsed": "0x5208",
+            "gasLimit": "0x1c9c380",
+            "extraData": "0x1234",
+            "logsBloom": format!("0x{}", "0".repeat(512)),
+            "timestamp": "0x65f0a000",
+            "difficulty": "0x1000000000000000000",
+            "totalDifficulty": "0xc70d815d562d3cfa955",
+            "uncles": [],
+            "transactions": [],
+            "size": "0x220",
+            "mixHash": format!("{:?}", H256::repeat_byte(8)),
+            "nonce": "0x0000000000000042",
+            "withdrawals": [{
+                "index": "0x10",
+                "validatorIndex": "0x20",
+                "address": format!("{:?}", Address::repeat_byte(9)),
+                "amount": "0x3b9aca00",
+            }],
+        }))
+        .unwrap();
+
+        let block = Block::from_ethers(block).unwrap();
+        assert_eq!(block.difficulty, Some(U256::from(2).pow(72.into())));
+        assert_eq!(
+            block.total_difficulty.unwrap().to_string(),
+            "58750003716598352816469"
+        );
+        assert_eq!(block.base_fee_per_gas, None);
+        assert_eq!(
+            block.extra_data.as_ref().unwrap().to_vec(),
+            vec![0x12, 0x34]
+        );
+        assert_eq!(block.withdrawals[0].validator_index, 32);
+
+        // Serialized blocks are read back the same, as are decimal Chainbase numbers
+        let round_trip: Block = from_value(serde_json::to_value(&block).unwrap()).unwrap();
+        assert_eq!(round_trip.total_difficulty, block.total_difficulty);
+        assert_eq!(round_trip.withdrawals[0].amount, U256::from(1_000_000_000));
+
+        let mut value = serde_json::to_value(&block).unwrap();
+        value["total_difficulty"] = json!(58750003716598352816469u128);
+        value["difficulty"] = json!(0);
+        let chainbase: Block = from_value(value).unwrap();
+        assert_eq!(chainbase.total_difficulty, block.total_difficulty);
+        assert_eq!(chainbase.difficulty, Some(U256::zero()));
+    }
+
+    #[test]
+    fn fields_missing_from_datasets_are_unset() {
+        let block: Block = from_value(json!({
+            "number": 100,
+            "timestamp": 1_700_000_000,
+            "hash": H256::repeat_byte(1),
+            "parent_hash": H256::repeat_byte(2),
+            "miner": Address::repeat_byte(3),
+            "gas_limit": 30_000_000,
+            "gas_used": 21_000,
+        }))
+        .unwrap();
+        assert_eq!(
+            (
+                block.transaction_count,
+                block.nonce,
+                block.difficulty,
+                block.size
+            ),
+            (None, None, None, None)
+        );
+    }
+}
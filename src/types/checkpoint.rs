@@ -0,0 +1,84 @@
+use std::collections::HashSet;
+
+use ethers::types::H256;
+use serde::{Deserialize, Serialize};
+
+use crate::dumper::Insertable;
+
+/// Block of a provider chain whose results are persisted, written in the same transaction
+/// as them so a restart resumes right after the last one. The checkpoints of the blocks
+/// before it are kept to walk back a reorg, the ones after it are dropped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub chain_id: u64,
+    pub block_number: u64,
+    pub block_hash: H256,
+    /// Blocks before it whose checkpoints are kept
+    pub retained: u64,
+}
+
+impl Insertable for Checkpoint {
+    const INSERT_QUERY: &'static str =
+        "WITH v (chain_id, block_number, block_hash, retained) AS (VALUES {values}),
+    n AS (
+        SELECT chain_id, max(block_number) AS newest, max(retained) AS retained
+        FROM v GROUP BY chain_id
+    ),
+    inserted AS (
+        INSERT INTO checkpoints (chain_id, block_number, block_hash)
+        SELECT v.chain_id, v.block_number, v.block_hash FROM v JOIN n USING (chain_id)
+        WHERE v.block_number >= n.newest - n.retained
+        ON CONFLICT (chain_id, block_number) DO UPDATE SET block_hash = EXCLUDED.block_hash
+    )
+    DELETE FROM checkpoints USING n
+    WHERE checkpoints.chain_id = n.chain_id
+        AND (checkpoints.block_number < n.newest - n.retained OR checkpoints.block_number > n.newest)";
+
+    fn value(&self) -> String {
+        format!(
+            "({},{},'{:?}',{})",
+            self.chain_id, self.block_number, self.block_hash, self.retained
+        )
+    }
+
+    /// Keep the last checkpoint of each block, a block is sent again after a reorg
+    fn remove_duplicates(v: &mut Vec<String>) {
+        let mut seen = HashSet::new();
+        v.reverse();
+        v.retain(|e| seen.insert(e.split(',').take(2).collect::<Vec<_>>().join(",")));
+        v.reverse();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn last_checkpoint_per_block() {
+        let checkpoint = |chain_id: u64, block_number: u64, hash: u8| {
+            Checkpoint {
+                chain_id,
+                block_number,
+                block_hash: H256::repeat_byte(hash),
+                retained: 64,
+            }
+            .value()
+        };
+        let mut values = vec![
+            checkpoint(1, 10, 1),
+            checkpoint(2, 10, 1),
+            checkpoint(1, 11, 1),
+            checkpoint(1, 10, 2),
+        ];
+        Checkpoint::remove_duplicates(&mut values);
+        assert_eq!(
+            values,
+            vec![
+                checkpoint(2, 10, 1),
+                checkpoint(1, 11, 1),
+                checkpoint(1, 10, 2)
+            ]
+        );
+    }
+}
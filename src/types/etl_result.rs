@@ -1,21 +1,25 @@
 use ethers::{
-    types::{Address, Bytes, H256, H32, U256},
+    types::{Address, Bytes, TransactionReceipt, H256, H32, U256},
     utils::to_checksum,
 };
 use serde::{Deserialize, Serialize};
 use serde_json::to_string_pretty;
 use std::{
-    collections::HashSet,
+    collections::{HashMap, HashSet},
     fmt::{Display, Formatter},
+    mem::size_of,
 };
 use structstruck::strike;
 
 use crate::dumper::Insertable;
 
-use super::Block;
+use crate::config::ChainFamily;
+
+use super::{Block, L1Data, Log, LogWithChainId, Withdrawal};
 
 strike! {
     #[strikethrough[derive(Debug, Clone, Serialize, Deserialize)]]
+    #[allow(clippy::large_enum_variant)]
     pub enum EtlResult {
         BlockWithChainId(struct {
             pub chain_id: u64,
@@ -42,6 +46,9 @@ strike! {
             pub from_address: Address,
             pub to_address: Address,
             pub closest_address: HashSet<Address>,
+            /// Degree 0 and 1 contracts of the transaction, whose logs are kept
+            #[serde(default)]
+            pub contract_addresses: HashSet<Address>,
             pub function_signature: H32,
             pub transaction_hash: H256,
             pub transaction_index: u32,
@@ -63,6 +70,58 @@ strike! {
             pub ec_pairing_input_sizes: Vec<u32>,
             pub ec_recover_addresses: HashSet<Address>,
             pub error: Option<String>,
+            /// Receipt fields, from the chain's transactions topic or the provider's receipts
+            pub status: Option<u64>,
+            pub gas_price: Option<U256>,
+            pub nonce: Option<u64>,
+            pub effective_gas_price: Option<U256>,
+            /// Gas used by the receipt times the effective gas price, in wei
+            pub fee: Option<U256>,
+            pub l1: L1Data,
+            /// Versioned hashes of the blobs carried by the transaction
+            pub blob_versioned_hashes: Vec<H256>,
+            /// Versioned hashes given to the point evaluation precompile, the blobs verified
+            /// by the transaction
+            pub point_evaluation_hashes: Vec<H256>,
+            /// Simulated from the mempool, not mined yet
+            #[serde(default)]
+            pub provisional: bool,
+        }),
+        /// Log emitted by a degree 0 or 1 contract of a transaction result
+        Log(LogWithChainId),
+        /// Blob carried by a transaction, links the versioned hashes verified on-chain to
+        /// the transaction that posted them
+        Blob(struct {
+            pub chain_id: u64,
+            pub versioned_hash: H256,
+            pub transaction_hash: H256,
+            pub block_number: u64,
+            /// Position of the blob in the transaction
+            pub index: u32,
+        }),
+        /// Transaction included in a processed block, its provisional row is removed
+        MinedTransaction(struct {
+            pub chain_id: u64,
+            pub transaction_hash: H256,
+        }),
+        /// Receipt fields of a transaction whose trace was not joined in memory, set on its
+        /// stored row when there is one
+        UnmatchedDetails(struct {
+            pub chain_id: u64,
+            pub transaction_hash: H256,
+            pub status: Option<u64>,
+            pub gas_price: Option<U256>,
+            pub nonce: Option<u64>,
+            pub effective_gas_price: Option<U256>,
+            pub fee: Option<U256>,
+            pub l1: L1Data,
+            pub blob_versioned_hashes: Vec<H256>,
+        }),
+        /// Log of a transaction whose trace was not joined in memory, kept when it was
+        /// emitted by one of the contracts of its stored row
+        UnmatchedLog(struct {
+            pub chain_id: u64,
+            pub log: Log,
         }),
     }
 }
@@ -85,6 +144,36 @@ impl From<BlockWithChainId> for EtlResult {
     }
 }
 
+impl From<LogWithChainId> for EtlResult {
+    fn from(value: LogWithChainId) -> Self {
+        Self::Log(value)
+    }
+}
+
+impl From<Blob> for EtlResult {
+    fn from(value: Blob) -> Self {
+        Self::Blob(value)
+    }
+}
+
+impl From<MinedTransaction> for EtlResult {
+    fn from(value: MinedTransaction) -> Self {
+        Self::MinedTransaction(value)
+    }
+}
+
+impl From<UnmatchedDetails> for EtlResult {
+    fn from(value: UnmatchedDetails) -> Self {
+        Self::UnmatchedDetails(value)
+    }
+}
+
+impl From<UnmatchedLog> for EtlResult {
+    fn from(value: UnmatchedLog) -> Self {
+        Self::UnmatchedLog(value)
+    }
+}
+
 impl Display for Contract {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(
@@ -115,6 +204,16 @@ impl Display for BlockWithChainId {
     }
 }
 
+impl Display for Blob {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            to_string_pretty(self).expect("Failed to serialize blob")
+        )
+    }
+}
+
 impl Display for EtlResult {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -123,6 +222,19 @@ impl Display for EtlResult {
             Self::BlockWithChainId(block) => {
                 write!(f, "Block: {}", block)
             }
+            Self::Log(log) => write!(f, "Log: {}", log),
+            Self::Blob(blob) => write!(f, "Blob: {}", blob),
+            Self::MinedTransaction(mined) => {
+                write!(f, "Mined transaction: {:?}", mined.transaction_hash)
+            }
+            Self::UnmatchedDetails(details) => {
+                write!(f, "Unmatched details: {:?}", details.transaction_hash)
+            }
+            Self::UnmatchedLog(log) => write!(
+                f,
+                "Unmatched log: {:?} {}",
+                log.log.transaction_hash, log.log.log_index
+            ),
         }
     }
 }
@@ -133,8 +245,49 @@ impl EtlResult {
             Self::Contract(contract) => contract.chain_id,
             Self::Transaction(transaction) => transaction.chain_id,
             Self::BlockWithChainId(block) => block.chain_id,
+            Self::Log(log) => log.chain_id,
+            Self::Blob(blob) => blob.chain_id,
+            Self::MinedTransaction(mined) => mined.chain_id,
+            Self::UnmatchedDetails(details) => details.chain_id,
+            Self::UnmatchedLog(log) => log.chain_id,
         }
     }
+
+    /// Approximate memory used by the result in bytes, to bound the dumper's batches
+    pub fn size(&self) -> usize {
+        let heap = match self {
+            Self::Contract(contract) => {
+                contract.function_signatures.len() * size_of::<H32>()
+                    + contract.ec_pairing_input_sizes.len() * size_of::<u32>()
+                    + contract.call.len() * size_of::<Address>()
+            }
+            Self::Transaction(transaction) => {
+                transaction.input.len()
+                    + (transaction.closest_address.len()
+                        + transaction.contract_addresses.len()
+                        + transaction.ec_recover_addresses.len())
+                        * size_of::<Address>()
+                    + transaction.ec_pairing_input_sizes.len() * size_of::<u32>()
+                    + transaction.error.as_ref().map_or(0, String::len)
+                    + (transaction.blob_versioned_hashes.len()
+                        + transaction.point_evaluation_hashes.len())
+                        * size_of::<H256>()
+            }
+            Self::BlockWithChainId(block) => {
+                block.block.extra_data.as_ref().map_or(0, |e| e.len())
+                    + block.block.withdrawals.len() * size_of::<Withdrawal>()
+            }
+            Self::Log(log) => log.log.data.len() + log.log.topics.len() * size_of::<H256>(),
+            Self::UnmatchedDetails(details) => {
+                details.blob_versioned_hashes.len() * size_of::<H256>()
+            }
+            Self::UnmatchedLog(log) => {
+                log.log.data.len() + log.log.topics.len() * size_of::<H256>()
+            }
+            Self::Blob(_) | Self::MinedTransaction(_) => 0,
+        };
+        size_of::<Self>() + heap
+    }
 }
 
 impl AsRef<Transaction> for Transaction {
@@ -149,18 +302,69 @@ impl AsRef<Contract> for Contract {
     }
 }
 
+impl Transaction {
+    /// Take the status and gas cost from the transaction's receipt
+    pub fn apply_receipt(&mut self, family: ChainFamily, receipt: &TransactionReceipt) {
+        self.status = receipt.status.map(|s| s.as_u64());
+        self.effective_gas_price = receipt.effective_gas_price;
+        self.fee = receipt
+            .gas_used
+            .zip(receipt.effective_gas_price)
+            .map(|(gas, price)| gas * price);
+        self.l1 = L1Data::from_receipt(family, receipt);
+    }
+}
+
 impl Insertable for Transaction {
     const INSERT_QUERY: &'static str = "INSERT INTO transactions (
-        chain_id, transaction_hash, from_address, to_address, closest_address,
+        chain_id, transaction_hash, from_address, to_address, closest_address, contract_addresses,
         function_signature, transaction_index,
         block_number, block_timestamp, block_hash, value, input,
         gas_used_total, gas_used_first_degree, gas_used_second_degree,
-        ec_recover_count, ec_add_count, ec_mul_count, ec_pairing_count, ec_pairing_input_sizes, ec_recover_addresses, error
-    ) VALUES {values} ON CONFLICT (chain_id, transaction_hash) DO NOTHING";
+        ec_recover_count, ec_add_count, ec_mul_count, ec_pairing_count, ec_pairing_input_sizes, ec_recover_addresses, error,
+        status, gas_price, nonce, effective_gas_price, fee,
+        l1_block_number, l1_gas_used, l1_fee, l1_blob_base_fee,
+        blob_versioned_hashes, point_evaluation_hashes, provisional
+    ) VALUES {values} ON CONFLICT (chain_id, transaction_hash) DO UPDATE SET
+    from_address = EXCLUDED.from_address,
+    to_address = EXCLUDED.to_address,
+    closest_address = EXCLUDED.closest_address,
+    contract_addresses = EXCLUDED.contract_addresses,
+    function_signature = EXCLUDED.function_signature,
+    transaction_index = EXCLUDED.transaction_index,
+    block_number = EXCLUDED.block_number,
+    block_timestamp = COALESCE(EXCLUDED.block_timestamp, transactions.block_timestamp),
+    block_hash = COALESCE(EXCLUDED.block_hash, transactions.block_hash),
+    value = EXCLUDED.value,
+    input = EXCLUDED.input,
+    gas_used_total = EXCLUDED.gas_used_total,
+    gas_used_first_degree = EXCLUDED.gas_used_first_degree,
+    gas_used_second_degree = EXCLUDED.gas_used_second_degree,
+    ec_recover_count = EXCLUDED.ec_recover_count,
+    ec_add_count = EXCLUDED.ec_add_count,
+    ec_mul_count = EXCLUDED.ec_mul_count,
+    ec_pairing_count = EXCLUDED.ec_pairing_count,
+    ec_pairing_input_sizes = EXCLUDED.ec_pairing_input_sizes,
+    ec_recover_addresses = EXCLUDED.ec_recover_addresses,
+    error = EXCLUDED.error,
+    point_evaluation_hashes = EXCLUDED.point_evaluation_hashes,
+    provisional = EXCLUDED.provisional,
+    status = COALESCE(EXCLUDED.status, transactions.status),
+    gas_price = COALESCE(EXCLUDED.gas_price, transactions.gas_price),
+    nonce = COALESCE(EXCLUDED.nonce, transactions.nonce),
+    effective_gas_price = COALESCE(EXCLUDED.effective_gas_price, transactions.effective_gas_price),
+    fee = COALESCE(EXCLUDED.fee, transactions.fee),
+    l1_block_number = COALESCE(EXCLUDED.l1_block_number, transactions.l1_block_number),
+    l1_gas_used = COALESCE(EXCLUDED.l1_gas_used, transactions.l1_gas_used),
+    l1_fee = COALESCE(EXCLUDED.l1_fee, transactions.l1_fee),
+    l1_blob_base_fee = COALESCE(EXCLUDED.l1_blob_base_fee, transactions.l1_blob_base_fee),
+    blob_versioned_hashes = CASE WHEN cardinality(EXCLUDED.blob_versioned_hashes) > 0
+        THEN EXCLUDED.blob_versioned_hashes ELSE transactions.blob_versioned_hashes END
+    WHERE transactions.provisional OR NOT EXCLUDED.provisional";
 
     fn value(&self) -> String {
         format!(
-            "({},'{:?}','{}','{}','{{{}}}','{:?}',{},{},{},{},{},'{}',{},{},{},{},{},{},{},'{{{}}}','{{{}}}',{})",
+            "({},'{:?}','{}','{}','{{{}}}','{{{}}}','{:?}',{},{},{},{},{},'{}',{},{},{},{},{},{},{},'{{{}}}','{{{}}}',{},{},{},{},{},{},{},{},{},{},'{{{}}}','{{{}}}',{})",
             self.chain_id,
             self.transaction_hash,
             to_checksum(&self.from_address, None),
@@ -170,6 +374,11 @@ impl Insertable for Transaction {
                 .map(|e| format!("\"{}\"", to_checksum(e, None)))
                 .collect::<Vec<_>>()
                 .join(","),
+            self.contract_addresses
+                .iter()
+                .map(|e| format!("\"{}\"", to_checksum(e, None)))
+                .collect::<Vec<_>>()
+                .join(","),
             self.function_signature,
             self.transaction_index,
             self.block_number,
@@ -198,14 +407,69 @@ impl Insertable for Transaction {
                 .map(|e| format!("\"{}\"", to_checksum(e, None)))
                 .collect::<Vec<_>>()
                 .join(","),
-            self.error.as_ref().map(|e| format!("'{}'", e)).unwrap_or("NULL".to_string())
+            self.error.as_ref().map(|e| format!("'{}'", e)).unwrap_or("NULL".to_string()),
+            self.status
+                .map(|e| e.to_string())
+                .unwrap_or("NULL".to_string()),
+            self.gas_price
+                .map(|e| e.to_string())
+                .unwrap_or("NULL".to_string()),
+            self.nonce
+                .map(|e| e.to_string())
+                .unwrap_or("NULL".to_string()),
+            self.effective_gas_price
+                .map(|e| e.to_string())
+                .unwrap_or("NULL".to_string()),
+            self.fee
+                .map(|e| e.to_string())
+                .unwrap_or("NULL".to_string()),
+            self.l1.block_number
+                .map(|e| e.to_string())
+                .unwrap_or("NULL".to_string()),
+            self.l1.gas_used
+                .map(|e| e.to_string())
+                .unwrap_or("NULL".to_string()),
+            self.l1.fee
+                .map(|e| e.to_string())
+                .unwrap_or("NULL".to_string()),
+            self.l1.blob_base_fee
+                .map(|e| e.to_string())
+                .unwrap_or("NULL".to_string()),
+            self.blob_versioned_hashes
+                .iter()
+                .map(|e| format!("\"{:?}\"", e))
+                .collect::<Vec<_>>()
+                .join(","),
+            self.point_evaluation_hashes
+                .iter()
+                .map(|e| format!("\"{:?}\"", e))
+                .collect::<Vec<_>>()
+                .join(","),
+            self.provisional
         )
     }
 
+    /// Keep the last value of each transaction, an enriched transaction can be sent again
+    /// and the upsert can't touch the same row twice. A mined transaction is kept over a
+    /// provisional one simulated later.
     fn remove_duplicates(v: &mut Vec<String>) {
-        v.reverse();
-        v.dedup_by(|v1, v2| v1.split(',').take(2).eq(v2.split(',').take(2)));
-        v.reverse();
+        let provisional = |e: &str| e.ends_with(",true)");
+        let mut kept = HashMap::<String, usize>::new();
+        for (i, e) in v.iter().enumerate() {
+            let key = e.split(',').take(2).collect::<Vec<_>>().join(",");
+            match kept.get(&key) {
+                Some(&j) if !provisional(&v[j]) && provisional(e) => {}
+                _ => {
+                    kept.insert(key, i);
+                }
+            }
+        }
+        let kept = kept.into_values().collect::<HashSet<_>>();
+        let mut i = 0;
+        v.retain(|_| {
+            i += 1;
+            kept.contains(&(i - 1))
+        });
     }
 }
 
@@ -264,39 +528,97 @@ impl Contract {
 }
 
 impl Insertable for BlockWithChainId {
-    const INSERT_QUERY: &'static str = "INSERT INTO blocks (chain_id, number, timestamp, hash, parent_hash, transaction_count, nonce, miner, difficulty, total_difficulty, size, gas_limit, gas_used, base_fee_per_gas)
+    const INSERT_QUERY: &'static str = "INSERT INTO blocks (chain_id, number, timestamp, hash, parent_hash, transaction_count, nonce, miner, difficulty, total_difficulty, size, gas_limit, gas_used, base_fee_per_gas, l1_block_number, blob_gas_used, excess_blob_gas, parent_beacon_block_root,
+    uncles_hash, state_root, transactions_root, receipts_root, logs_bloom, extra_data, mix_hash, withdrawals_root, withdrawal_count)
     VALUES {values} 
     ON CONFLICT (chain_id, number) DO UPDATE SET
     timestamp = EXCLUDED.timestamp,
     hash = EXCLUDED.hash,
     parent_hash = EXCLUDED.parent_hash,
-    transaction_count = EXCLUDED.transaction_count,
-    nonce = EXCLUDED.nonce,
+    transaction_count = COALESCE(EXCLUDED.transaction_count, blocks.transaction_count),
+    nonce = COALESCE(EXCLUDED.nonce, blocks.nonce),
     miner = EXCLUDED.miner,
-    difficulty = EXCLUDED.difficulty,
-    total_difficulty = EXCLUDED.total_difficulty,
-    size = EXCLUDED.size,
+    difficulty = COALESCE(EXCLUDED.difficulty, blocks.difficulty),
+    total_difficulty = COALESCE(EXCLUDED.total_difficulty, blocks.total_difficulty),
+    size = COALESCE(EXCLUDED.size, blocks.size),
     gas_limit = EXCLUDED.gas_limit,
     gas_used = EXCLUDED.gas_used,
-    base_fee_per_gas = EXCLUDED.base_fee_per_gas";
+    base_fee_per_gas = COALESCE(EXCLUDED.base_fee_per_gas, blocks.base_fee_per_gas),
+    l1_block_number = COALESCE(EXCLUDED.l1_block_number, blocks.l1_block_number),
+    blob_gas_used = COALESCE(EXCLUDED.blob_gas_used, blocks.blob_gas_used),
+    excess_blob_gas = COALESCE(EXCLUDED.excess_blob_gas, blocks.excess_blob_gas),
+    parent_beacon_block_root = COALESCE(EXCLUDED.parent_beacon_block_root, blocks.parent_beacon_block_root),
+    uncles_hash = COALESCE(EXCLUDED.uncles_hash, blocks.uncles_hash),
+    state_root = COALESCE(EXCLUDED.state_root, blocks.state_root),
+    transactions_root = COALESCE(EXCLUDED.transactions_root, blocks.transactions_root),
+    receipts_root = COALESCE(EXCLUDED.receipts_root, blocks.receipts_root),
+    logs_bloom = COALESCE(EXCLUDED.logs_bloom, blocks.logs_bloom),
+    extra_data = COALESCE(EXCLUDED.extra_data, blocks.extra_data),
+    mix_hash = COALESCE(EXCLUDED.mix_hash, blocks.mix_hash),
+    withdrawals_root = COALESCE(EXCLUDED.withdrawals_root, blocks.withdrawals_root),
+    withdrawal_count = COALESCE(EXCLUDED.withdrawal_count, blocks.withdrawal_count)";
 
     fn value(&self) -> String {
+        let quoted =
+            |h: Option<String>| h.map(|h| format!("'{}'", h)).unwrap_or("NULL".to_string());
+        let hash = |h: Option<H256>| quoted(h.map(|h| format!("{:?}", h)));
         format!(
-            "({},{},{},'{:?}','{:?}',{},'{:?}','{}',{},{},{},{},{},{})",
+            "({},{},{},'{:?}','{:?}',{},{},'{}',{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{})",
             self.chain_id,
             self.block.number,
             self.block.timestamp,
             self.block.hash,
             self.block.parent_hash,
-            self.block.transaction_count,
-            self.block.nonce,
+            self.block
+                .transaction_count
+                .map(|e| e.to_string())
+                .unwrap_or("NULL".to_string()),
+            quoted(self.block.nonce.map(|n| format!("{:?}", n))),
             to_checksum(&self.block.miner, None),
-            self.block.difficulty,
-            self.block.total_difficulty,
-            self.block.size,
+            self.block
+                .difficulty
+                .map(|e| e.to_string())
+                .unwrap_or("NULL".to_string()),
+            self.block
+                .total_difficulty
+                .map(|e| e.to_string())
+                .unwrap_or("NULL".to_string()),
+            self.block
+                .size
+                .map(|e| e.to_string())
+                .unwrap_or("NULL".to_string()),
             self.block.gas_limit,
             self.block.gas_used,
-            self.block.base_fee_per_gas,
+            self.block
+                .base_fee_per_gas
+                .map(|e| e.to_string())
+                .unwrap_or("NULL".to_string()),
+            self.block
+                .l1_block_number
+                .map(|e| e.to_string())
+                .unwrap_or("NULL".to_string()),
+            self.block
+                .blob_gas_used
+                .map(|e| e.to_string())
+                .unwrap_or("NULL".to_string()),
+            self.block
+                .excess_blob_gas
+                .map(|e| e.to_string())
+                .unwrap_or("NULL".to_string()),
+            hash(self.block.parent_beacon_block_root),
+            hash(self.block.uncles_hash),
+            hash(self.block.state_root),
+            hash(self.block.transactions_root),
+            hash(self.block.receipts_root),
+            quoted(self.block.logs_bloom.map(|b| format!("{:?}", b))),
+            quoted(self.block.extra_data.as_ref().map(ToString::to_string)),
+            hash(self.block.mix_hash),
+            hash(self.block.withdrawals_root),
+            // Unknown when the source has neither the withdrawals nor their root
+            match self.block.withdrawals.is_empty() && self.block.withdrawals_root.is_none() {
+                true => "NULL".to_string(),
+                false => self.block.withdrawals.len().to_string(),
+            },
         )
     }
 
@@ -306,3 +628,235 @@ impl Insertable for BlockWithChainId {
         v.reverse();
     }
 }
+
+impl BlockWithChainId {
+    pub fn withdrawals(&self) -> impl Iterator<Item = WithdrawalWithChainId> + '_ {
+        self.block
+            .withdrawals
+            .iter()
+            .map(|withdrawal| WithdrawalWithChainId {
+                chain_id: self.chain_id,
+                block_number: self.block.number,
+                withdrawal: withdrawal.clone(),
+            })
+    }
+}
+
+/// Withdrawal of a block result, stored in its own table
+#[derive(Debug, Clone)]
+pub struct WithdrawalWithChainId {
+    pub chain_id: u64,
+    pub block_number: u64,
+    pub withdrawal: Withdrawal,
+}
+
+impl Insertable for WithdrawalWithChainId {
+    const INSERT_QUERY: &'static str =
+        "INSERT INTO withdrawals (chain_id, index, block_number, validator_index, address, amount)
+    VALUES {values}
+    ON CONFLICT (chain_id, index) DO UPDATE SET
+    block_number = EXCLUDED.block_number,
+    validator_index = EXCLUDED.validator_index,
+    address = EXCLUDED.address,
+    amount = EXCLUDED.amount";
+
+    fn value(&self) -> String {
+        format!(
+            "({},{},{},{},'{}',{})",
+            self.chain_id,
+            self.withdrawal.index,
+            self.block_number,
+            self.withdrawal.validator_index,
+            to_checksum(&self.withdrawal.address, None),
+            self.withdrawal.amount,
+        )
+    }
+
+    fn remove_duplicates(v: &mut Vec<String>) {
+        let mut seen = HashSet::new();
+        v.reverse();
+        v.retain(|e| seen.insert(e.split(',').take(2).collect::<Vec<_>>().join(",")));
+        v.reverse();
+    }
+}
+
+impl Blob {
+    /// Blob results of a transaction, in the order of its versioned hashes
+    pub fn from_hashes(
+        chain_id: u64,
+        transaction_hash: H256,
+        block_number: u64,
+        versioned_hashes: &[H256],
+    ) -> Vec<EtlResult> {
+        versioned_hashes
+            .iter()
+            .enumerate()
+            .map(|(index, versioned_hash)| {
+                Blob {
+                    chain_id,
+                    versioned_hash: *versioned_hash,
+                    transaction_hash,
+                    block_number,
+                    index: index as u32,
+                }
+                .into()
+            })
+            .collect()
+    }
+}
+
+impl Insertable for Blob {
+    const INSERT_QUERY: &'static str =
+        "INSERT INTO blobs (chain_id, versioned_hash, transaction_hash, block_number, index)
+    VALUES {values}
+    ON CONFLICT (chain_id, versioned_hash, transaction_hash) DO NOTHING";
+
+    fn value(&self) -> String {
+        format!(
+            "({},'{:?}','{:?}',{},{})",
+            self.chain_id,
+            self.versioned_hash,
+            self.transaction_hash,
+            self.block_number,
+            self.index,
+        )
+    }
+
+    fn remove_duplicates(v: &mut Vec<String>) {
+        let mut seen = HashSet::new();
+        v.retain(|e| seen.insert(e.split(',').take(3).collect::<Vec<_>>().join(",")));
+    }
+}
+
+impl Insertable for MinedTransaction {
+    const INSERT_QUERY: &'static str = "DELETE FROM transactions
+    WHERE provisional AND (chain_id, transaction_hash) IN (VALUES {values})";
+
+    fn value(&self) -> String {
+        format!("({},'{:?}')", self.chain_id, self.transaction_hash)
+    }
+}
+
+impl Insertable for UnmatchedDetails {
+    const INSERT_QUERY: &'static str = "UPDATE transactions SET
+    status = COALESCE(v.status, transactions.status),
+    gas_price = COALESCE(v.gas_price, transactions.gas_price),
+    nonce = COALESCE(v.nonce, transactions.nonce),
+    effective_gas_price = COALESCE(v.effective_gas_price, transactions.effective_gas_price),
+    fee = COALESCE(v.fee, transactions.fee),
+    l1_block_number = COALESCE(v.l1_block_number, transactions.l1_block_number),
+    l1_gas_used = COALESCE(v.l1_gas_used, transactions.l1_gas_used),
+    l1_fee = COALESCE(v.l1_fee, transactions.l1_fee),
+    l1_blob_base_fee = COALESCE(v.l1_blob_base_fee, transactions.l1_blob_base_fee),
+    blob_versioned_hashes = CASE WHEN cardinality(v.blob_versioned_hashes) > 0
+        THEN v.blob_versioned_hashes ELSE transactions.blob_versioned_hashes END
+    FROM (VALUES {values}) AS v (
+        chain_id, transaction_hash, status, gas_price, nonce, effective_gas_price, fee,
+        l1_block_number, l1_gas_used, l1_fee, l1_blob_base_fee, blob_versioned_hashes
+    )
+    WHERE transactions.chain_id = v.chain_id AND transactions.transaction_hash = v.transaction_hash
+        AND NOT transactions.provisional";
+
+    /// Typed explicitly, the columns of a `VALUES` list of `NULL`s would be text
+    fn value(&self) -> String {
+        let or_null = |v: Option<String>| v.unwrap_or("NULL".to_string());
+        format!(
+            "({},'{:?}',{}::bigint,{}::numeric,{}::numeric,{}::numeric,{}::numeric,{}::bigint,{}::numeric,{}::numeric,{}::numeric,'{{{}}}'::text[])",
+            self.chain_id,
+            self.transaction_hash,
+            or_null(self.status.map(|e| e.to_string())),
+            or_null(self.gas_price.map(|e| e.to_string())),
+            or_null(self.nonce.map(|e| e.to_string())),
+            or_null(self.effective_gas_price.map(|e| e.to_string())),
+            or_null(self.fee.map(|e| e.to_string())),
+            or_null(self.l1.block_number.map(|e| e.to_string())),
+            or_null(self.l1.gas_used.map(|e| e.to_string())),
+            or_null(self.l1.fee.map(|e| e.to_string())),
+            or_null(self.l1.blob_base_fee.map(|e| e.to_string())),
+            self.blob_versioned_hashes
+                .iter()
+                .map(|e| format!("\"{:?}\"", e))
+                .collect::<Vec<_>>()
+                .join(","),
+        )
+    }
+}
+
+impl Insertable for UnmatchedLog {
+    const INSERT_QUERY: &'static str = "INSERT INTO logs (
+        chain_id, transaction_hash, log_index, address, topics, data, block_number
+    ) SELECT v.chain_id, v.transaction_hash, v.log_index, v.address, v.topics, v.data, v.block_number
+    FROM (VALUES {values}) AS v (
+        chain_id, transaction_hash, log_index, address, topics, data, block_number
+    )
+    JOIN transactions ON transactions.chain_id = v.chain_id
+        AND transactions.transaction_hash = v.transaction_hash
+    WHERE NOT transactions.provisional AND v.address = ANY(transactions.contract_addresses)
+    ON CONFLICT (chain_id, transaction_hash, log_index) DO NOTHING";
+
+    fn value(&self) -> String {
+        format!(
+            "({},'{:?}',{},'{}','{{{}}}'::text[],'{}',{})",
+            self.chain_id,
+            self.log.transaction_hash,
+            self.log.log_index,
+            to_checksum(&self.log.address, None),
+            self.log
+                .topics
+                .iter()
+                .map(|e| format!("\"{:?}\"", e))
+                .collect::<Vec<_>>()
+                .join(","),
+            self.log.data,
+            self.log.block_number,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unmatched_details_are_typed() {
+        let details = UnmatchedDetails {
+            chain_id: 1,
+            transaction_hash: H256::zero(),
+            status: Some(1),
+            gas_price: None,
+            nonce: Some(7),
+            effective_gas_price: None,
+            fee: None,
+            l1: L1Data::default(),
+            blob_versioned_hashes: vec![],
+        };
+        assert_eq!(
+            details.value(),
+            format!(
+                "(1,'{:?}',1::bigint,NULL::numeric,7::numeric,NULL::numeric,NULL::numeric,NULL::bigint,NULL::numeric,NULL::numeric,NULL::numeric,'{{}}'::text[])",
+                H256::zero()
+            )
+        );
+    }
+
+    #[test]
+    fn mined_transaction_kept_over_provisional() {
+        let mut values = vec![
+            "(1,'0xaa',1,false)".to_string(),
+            "(1,'0xbb',1,true)".to_string(),
+            "(1,'0xaa',2,true)".to_string(),
+            "(1,'0xbb',2,true)".to_string(),
+            "(1,'0xcc',1,false)".to_string(),
+            "(1,'0xcc',2,false)".to_string(),
+        ];
+        Transaction::remove_duplicates(&mut values);
+        assert_eq!(
+            values,
+            vec![
+                "(1,'0xaa',1,false)",
+                "(1,'0xbb',2,true)",
+                "(1,'0xcc',2,false)"
+            ]
+        );
+    }
+}
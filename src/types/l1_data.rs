@@ -0,0 +1,129 @@
+use ethers::types::{OtherFields, TransactionReceipt, U256, U64};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::config::ChainFamily;
+
+use super::TransactionDetails;
+
+/// L1 data cost of a rollup transaction, empty on Ethereum
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct L1Data {
+    /// L1 block the transaction was sequenced at, Arbitrum only
+    pub block_number: Option<u64>,
+    /// Gas spent for posting the transaction data, counted in L2 gas on Arbitrum
+    pub gas_used: Option<u64>,
+    /// Cost of the transaction data on L1 in wei, already part of the fee on Arbitrum
+    pub fee: Option<U256>,
+    pub blob_base_fee: Option<U256>,
+}
+
+impl L1Data {
+    pub fn from_receipt(family: ChainFamily, receipt: &TransactionReceipt) -> Self {
+        let other = &receipt.other;
+        match family {
+            ChainFamily::Ethereum => Self::default(),
+            ChainFamily::Arbitrum => {
+                let gas_used = field::<U64>(other, "gasUsedForL1").map(|g| g.as_u64());
+                Self {
+                    block_number: field::<U64>(other, "l1BlockNumber").map(|n| n.as_u64()),
+                    gas_used,
+                    fee: arbitrum_fee(gas_used, receipt.effective_gas_price),
+                    blob_base_fee: None,
+                }
+            }
+            ChainFamily::Optimism => Self {
+                block_number: None,
+                gas_used: field::<U64>(other, "l1GasUsed").map(|g| g.as_u64()),
+                fee: field(other, "l1Fee"),
+                blob_base_fee: field(other, "l1BlobBaseFee"),
+            },
+        }
+    }
+
+    pub fn from_details(family: ChainFamily, details: &TransactionDetails) -> Self {
+        match family {
+            ChainFamily::Ethereum => Self::default(),
+            ChainFamily::Arbitrum => Self {
+                block_number: details.l1_block_number,
+                gas_used: details.l1_gas_used,
+                fee: arbitrum_fee(details.l1_gas_used, details.effective_gas_price),
+                blob_base_fee: None,
+            },
+            ChainFamily::Optimism => Self {
+                block_number: None,
+                gas_used: details.l1_gas_used,
+                fee: details.l1_fee,
+                blob_base_fee: details.l1_blob_base_fee,
+            },
+        }
+    }
+}
+
+/// Arbitrum charges the L1 data as extra L2 gas at the transaction's gas price
+fn arbitrum_fee(gas_used: Option<u64>, effective_gas_price: Option<U256>) -> Option<U256> {
+    gas_used
+        .zip(effective_gas_price)
+        .map(|(gas, price)| price * gas)
+}
+
+fn field<T: DeserializeOwned>(other: &OtherFields, key: &str) -> Option<T> {
+    other.get_deserialized(key).and_then(Result::ok)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{from_value, json};
+
+    use super::*;
+
+    fn receipt(extra: serde_json::Value) -> TransactionReceipt {
+        let mut receipt = json!({
+            "transactionHash": "0x0000000000000000000000000000000000000000000000000000000000000001",
+            "transactionIndex": "0x0",
+            "from": "0x0000000000000000000000000000000000000000",
+            "cumulativeGasUsed": "0x5208",
+            "gasUsed": "0x5208",
+            "logs": [],
+            "logsBloom": format!("0x{}", "0".repeat(512)),
+            "effectiveGasPrice": "0x3b9aca00",
+        });
+        receipt
+            .as_object_mut()
+            .unwrap()
+            .extend(extra.as_object().unwrap().clone());
+        from_value(receipt).unwrap()
+    }
+
+    #[test]
+    fn l1_data_by_family() {
+        let arbitrum = receipt(json!({"gasUsedForL1": "0x10", "l1BlockNumber": "0x12d687"}));
+        assert_eq!(
+            L1Data::from_receipt(ChainFamily::Arbitrum, &arbitrum),
+            L1Data {
+                block_number: Some(1_234_567),
+                gas_used: Some(16),
+                fee: Some(U256::from(16_000_000_000u64)),
+                blob_base_fee: None,
+            }
+        );
+        assert_eq!(
+            L1Data::from_receipt(ChainFamily::Ethereum, &arbitrum),
+            L1Data::default()
+        );
+
+        let optimism = receipt(json!({
+            "l1Fee": "0x2540be400",
+            "l1GasUsed": "0x640",
+            "l1BlobBaseFee": "0x1",
+        }));
+        assert_eq!(
+            L1Data::from_receipt(ChainFamily::Optimism, &optimism),
+            L1Data {
+                block_number: None,
+                gas_used: Some(1_600),
+                fee: Some(U256::from(10_000_000_000u64)),
+                blob_base_fee: Some(U256::one()),
+            }
+        );
+    }
+}
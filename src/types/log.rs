@@ -0,0 +1,115 @@
+use std::fmt::Display;
+
+use ethers::{
+    types::{Address, Bytes, Log as EtherLog, H256},
+    utils::to_checksum,
+};
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::to_string_pretty;
+
+use crate::dumper::Insertable;
+
+/// An event log from the Chainbase logs topic
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Log {
+    pub log_index: u32,
+    pub transaction_hash: H256,
+    pub address: Address,
+    pub data: Bytes,
+    /// Either a list or a comma separated string in exports
+    #[serde(deserialize_with = "topics_from_list")]
+    pub topics: Vec<H256>,
+    pub block_number: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogWithChainId {
+    pub chain_id: u64,
+    pub log: Log,
+}
+
+impl Log {
+    pub fn from_ethers(log: EtherLog) -> Option<Log> {
+        match log {
+            EtherLog {
+                address,
+                topics,
+                data,
+                block_number: Some(block_number),
+                transaction_hash: Some(transaction_hash),
+                log_index: Some(log_index),
+                ..
+            } => Some(Log {
+                log_index: log_index.as_u32(),
+                transaction_hash,
+                address,
+                data,
+                topics,
+                block_number: block_number.as_u64(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+fn topics_from_list<'de, D>(deserializer: D) -> Result<Vec<H256>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    use serde::de::Error;
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Topics {
+        List(Vec<H256>),
+        Joined(String),
+    }
+
+    match Topics::deserialize(deserializer)? {
+        Topics::List(topics) => Ok(topics),
+        Topics::Joined(topics) => topics
+            .split(',')
+            .map(str::trim)
+            .filter(|t| !t.is_empty())
+            .map(|t| t.parse().map_err(|e| Error::custom(format!("{e}: {t}"))))
+            .collect(),
+    }
+}
+
+impl Display for LogWithChainId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            to_string_pretty(self).expect("Failed to serialize log")
+        )
+    }
+}
+
+impl Insertable for LogWithChainId {
+    const INSERT_QUERY: &'static str = "INSERT INTO logs (
+        chain_id, transaction_hash, log_index, address, topics, data, block_number
+    ) VALUES {values} ON CONFLICT (chain_id, transaction_hash, log_index) DO NOTHING";
+
+    fn value(&self) -> String {
+        format!(
+            "({},'{:?}',{},'{}','{{{}}}','{}',{})",
+            self.chain_id,
+            self.log.transaction_hash,
+            self.log.log_index,
+            to_checksum(&self.log.address, None),
+            self.log
+                .topics
+                .iter()
+                .map(|e| format!("\"{:?}\"", e))
+                .collect::<Vec<_>>()
+                .join(","),
+            self.log.data,
+            self.log.block_number,
+        )
+    }
+
+    fn remove_duplicates(v: &mut Vec<String>) {
+        v.dedup_by(|v1, v2| v1.split(',').take(3).eq(v2.split(',').take(3)));
+    }
+}
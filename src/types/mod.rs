@@ -1,11 +1,19 @@
 mod block;
+mod checkpoint;
 mod etl_result;
 mod geth_trace;
+mod l1_data;
+mod log;
 mod trace;
 mod trace_tree;
+mod transaction_details;
 
 pub use block::*;
+pub use checkpoint::*;
 pub use etl_result::*;
 pub use geth_trace::*;
+pub use l1_data::*;
+pub use log::*;
 pub use trace::*;
 pub use trace_tree::*;
+pub use transaction_details::*;
@@ -4,7 +4,7 @@ use ethers::types::{
     Action, Address, Bytes, Call, CallResult, Res, Trace as EtherTrace, H256, U256,
 };
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
-use serde_json::{to_string, to_string_pretty, Number};
+use serde_json::{to_string, to_string_pretty, Value};
 
 use super::InnerCallFrame;
 
@@ -41,7 +41,7 @@ pub struct Trace {
     //pub item_timestamp: Option<String>,
 }
 
-fn value_as_string<S>(v: &Option<U256>, serializer: S) -> Result<S::Ok, S::Error>
+pub fn value_as_string<S>(v: &Option<U256>, serializer: S) -> Result<S::Ok, S::Error>
 where
     S: Serializer,
 {
@@ -51,15 +51,63 @@ where
         .serialize(serializer)
 }
 
+/// A 256 bits integer given as a JSON number or as a decimal or `0x` hex string
 pub fn value_from_string<'de, D>(deserializer: D) -> Result<Option<U256>, D::Error>
 where
     D: Deserializer<'de>,
 {
     use serde::de::Error;
-    Option::<Number>::deserialize(deserializer).and_then(|o| {
-        o.map(|n| U256::from_dec_str(&n.to_string()).map_err(|err| Error::custom(err.to_string())))
-            .transpose()
-    })
+    let value = match Option::<Value>::deserialize(deserializer)? {
+        None | Some(Value::Null) => return Ok(None),
+        Some(Value::Number(n)) => n.to_string(),
+        Some(Value::String(s)) => s,
+        Some(v) => return Err(Error::custom(format!("Invalid integer {}", v))),
+    };
+    match value.strip_prefix("0x") {
+        Some(hex) => U256::from_str_radix(hex, 16).map_err(|err| Error::custom(err.to_string())),
+        None => U256::from_dec_str(&value)
+            .ok()
+            .or_else(|| integer_from_decimal(&value))
+            .ok_or_else(|| Error::custom(format!("Invalid integer {}", value))),
+    }
+    .map(Some)
+}
+
+/// Exports sometimes write big integers in scientific notation like `1.5e21`, only those
+/// that are exactly an integer are accepted
+fn integer_from_decimal(value: &str) -> Option<U256> {
+    let (mantissa, exponent) = match value.split_once(['e', 'E']) {
+        Some((mantissa, exponent)) => (mantissa, exponent.parse::<i64>().ok()?),
+        None => (value, 0),
+    };
+    let (integer, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    let digits = format!("{integer}{fraction}");
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let mut exponent = exponent - fraction.len() as i64;
+    let mut digits = digits.as_str();
+    while exponent < 0 {
+        digits = digits.strip_suffix('0')?;
+        exponent += 1;
+    }
+    let digits = U256::from_dec_str(if digits.is_empty() { "0" } else { digits }).ok()?;
+    let scale = U256::from(10).checked_pow(U256::from(exponent))?;
+    digits.checked_mul(scale)
+}
+
+pub fn u256_as_string<S>(v: &U256, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    v.to_string().serialize(serializer)
+}
+
+pub fn u256_from_string<'de, D>(deserializer: D) -> Result<U256, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    value_from_string(deserializer).map(Option::unwrap_or_default)
 }
 
 impl Display for Trace {
@@ -163,3 +211,34 @@ impl Trace {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn value(v: Value) -> Result<Option<U256>, serde_json::Error> {
+        value_from_string(v)
+    }
+
+    #[test]
+    fn parse_integer_values() {
+        assert_eq!(value(json!("0x10")).unwrap(), Some(16.into()));
+        assert_eq!(value(json!("42")).unwrap(), Some(42.into()));
+        assert_eq!(value(json!(null)).unwrap(), None);
+        assert_eq!(
+            value(json!("1.5e21")).unwrap(),
+            Some(U256::from(15) * U256::exp10(20))
+        );
+        assert_eq!(value(json!("100.00")).unwrap(), Some(100.into()));
+        assert_eq!(value(json!("1e77")).unwrap(), Some(U256::exp10(77)));
+
+        // Not an integer, or too big
+        assert!(value(json!("1.5")).is_err());
+        assert!(value(json!("1e-3")).is_err());
+        assert!(value(json!("1e78")).is_err());
+        assert!(value(json!("-1")).is_err());
+        assert!(value(json!(".")).is_err());
+    }
+}
@@ -6,10 +6,11 @@ use std::{
 use crate::{
     constants::addresses::{
         EC_ADD_ADDRESS, EC_MUL_ADDRESS, EC_PAIRING_ADDRESS, EC_RECOVER_ADDRESS,
+        POINT_EVALUATION_ADDRESS,
     },
-    types::{Contract, EtlResult, GasUsed, Trace, Transaction},
+    types::{Contract, EtlResult, GasUsed, L1Data, Trace, Transaction},
 };
-use ethers::types::{Address, Bytes, H32};
+use ethers::types::{Address, Bytes, H256, H32};
 
 pub struct TraceTree {
     pub chain_id: u64,
@@ -22,12 +23,17 @@ pub struct TraceTree {
     /// from_address -> input_size
     pub ec_pairing_input_size_tree: HashMap<Address, Vec<u32>>,
     pub ec_recover_addresses: HashSet<Address>,
+    /// Versioned hashes given to the point evaluation precompile, in call order
+    pub point_evaluation_hashes: Vec<H256>,
     pub first_trace: Option<Trace>,
 }
 
 impl TraceTree {
-    const FIRST_DEGREE_FILTER_ADDRESSES: &'static [Address] =
-        &[EC_PAIRING_ADDRESS, EC_RECOVER_ADDRESS];
+    const FIRST_DEGREE_FILTER_ADDRESSES: &'static [Address] = &[
+        EC_PAIRING_ADDRESS,
+        EC_RECOVER_ADDRESS,
+        POINT_EVALUATION_ADDRESS,
+    ];
 
     pub fn new(chain_id: u64) -> Self {
         Self {
@@ -37,6 +43,7 @@ impl TraceTree {
             signature_tree: HashMap::new(),
             ec_pairing_input_size_tree: HashMap::new(),
             ec_recover_addresses: HashSet::new(),
+            point_evaluation_hashes: vec![],
             first_trace: None,
         }
     }
@@ -67,6 +74,7 @@ impl TraceTree {
     pub fn commit_filter(&self) -> bool {
         self.call_tree.contains_key(&EC_RECOVER_ADDRESS)
             || self.call_tree.contains_key(&EC_PAIRING_ADDRESS)
+            || self.call_tree.contains_key(&POINT_EVALUATION_ADDRESS)
     }
 
     pub fn commit(&self) -> Option<Vec<EtlResult>> {
@@ -88,7 +96,8 @@ impl TraceTree {
             true,
         ) = (&self.first_trace, self.commit_filter())
         {
-            // Addresses that called EC_PAIRING_ADDRESS or EC_RECOVER_ADDRESS
+            // Addresses that called EC_PAIRING_ADDRESS, EC_RECOVER_ADDRESS or
+            // POINT_EVALUATION_ADDRESS
             // (address, what it called)
             let mut first_degree_callers = HashMap::<Address, HashSet<Address>>::new();
             Self::FIRST_DEGREE_FILTER_ADDRESSES.iter().for_each(|a| {
@@ -185,6 +194,11 @@ impl TraceTree {
                 }
                 .copied()
                 .collect(),
+                contract_addresses: first_degree_callers
+                    .keys()
+                    .chain(second_degree_callers.keys())
+                    .copied()
+                    .collect(),
                 function_signature: {
                     input
                         .as_ref()
@@ -235,6 +249,15 @@ impl TraceTree {
                     .collect(),
                 ec_recover_addresses: self.ec_recover_addresses.clone(),
                 error: error.clone(),
+                status: None,
+                gas_price: None,
+                nonce: None,
+                effective_gas_price: None,
+                fee: None,
+                l1: L1Data::default(),
+                blob_versioned_hashes: vec![],
+                point_evaluation_hashes: self.point_evaluation_hashes.clone(),
+                provisional: false,
             }
             .into();
 
@@ -284,6 +307,14 @@ impl TraceTree {
                     );
             }
 
+            // The input starts with the versioned hash of the blob being verified
+            if to_address == POINT_EVALUATION_ADDRESS {
+                if let Some(b) = trace.input.as_ref().filter(|b| b.len() >= 32) {
+                    self.point_evaluation_hashes
+                        .push(H256::from_slice(&b[..32]));
+                }
+            }
+
             if to_address == EC_RECOVER_ADDRESS {
                 if let Some(b) = trace.output.as_ref() {
                     // If the output is less than 32 bytes, it's invalid address 0x0
@@ -302,12 +333,77 @@ impl TraceTree {
         }
     }
 
+    /// Drop the current transaction once it has been committed and no other follows
+    pub fn clear(&mut self) {
+        self.call_tree.clear();
+        self.gas_tree.clear();
+        self.signature_tree.clear();
+        self.ec_pairing_input_size_tree.clear();
+        self.ec_recover_addresses.clear();
+        self.point_evaluation_hashes.clear();
+        self.first_trace = None;
+    }
+
     pub fn reset<T: AsRef<Trace>>(&mut self, first_trace: T) {
         self.call_tree.clear();
         self.gas_tree.clear();
         self.signature_tree.clear();
         self.ec_pairing_input_size_tree.clear();
         self.ec_recover_addresses.clear();
+        self.point_evaluation_hashes.clear();
         self.first_trace = Some(first_trace.as_ref().clone());
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{from_value, json};
+
+    use super::*;
+
+    fn trace(to: Address, input: String, trace_address: Vec<u32>) -> Trace {
+        from_value(json!({
+            "transaction_index": 0,
+            "from_address": format!("{:?}", Address::repeat_byte(0xaa)),
+            "to_address": format!("{:?}", to),
+            "value": null,
+            "input": input,
+            "output": null,
+            "trace_type": "call",
+            "call_type": "call",
+            "reward_type": null,
+            "gas": 100000,
+            "gas_used": 50000,
+            "subtraces": 0,
+            "trace_address": trace_address,
+            "error": null,
+            "transaction_hash": format!("{:?}", H256::repeat_byte(1)),
+            "block_number": 100,
+            "block_timestamp": null,
+            "block_hash": null,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn commit_point_evaluation_hashes() {
+        let mut tree = TraceTree::new(1);
+        let root = trace(Address::repeat_byte(0xaa), "0x12345678".to_string(), vec![]);
+        tree.reset(&root);
+        tree.add_trace(&root);
+        assert!(tree.commit().is_none());
+
+        let versioned_hash = H256::repeat_byte(0x01);
+        tree.add_trace(trace(
+            POINT_EVALUATION_ADDRESS,
+            format!("{:?}{}", versioned_hash, "00".repeat(160)),
+            vec![0],
+        ));
+        let results = tree.commit().expect("point evaluation is committed");
+        let Some(EtlResult::Transaction(tx)) = results.last() else {
+            panic!("expected transaction");
+        };
+        assert_eq!(tx.point_evaluation_hashes, vec![versioned_hash]);
+        assert!(matches!(&results[0], EtlResult::Contract(c) if c.degree == 0));
+    }
+}
@@ -0,0 +1,53 @@
+use ethers::types::{H256, U256};
+use serde::{Deserialize, Serialize};
+
+use super::value_from_string;
+
+/// Receipt level fields of a transaction from the Chainbase transactions topic, used to
+/// enrich the transactions found from traces
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransactionDetails {
+    #[serde(alias = "transaction_hash")]
+    pub hash: H256,
+    pub block_number: u64,
+    pub nonce: u64,
+    #[serde(default, deserialize_with = "value_from_string")]
+    pub gas_price: Option<U256>,
+    /// 1 for success and 0 for failure, unknown before byzantium
+    #[serde(default, alias = "receipt_status")]
+    pub status: Option<u64>,
+    #[serde(default, alias = "receipt_gas_used")]
+    pub gas_used: Option<u64>,
+    #[serde(
+        default,
+        alias = "receipt_effective_gas_price",
+        deserialize_with = "value_from_string"
+    )]
+    pub effective_gas_price: Option<U256>,
+    /// Arbitrum's L1 block
+    #[serde(default)]
+    pub l1_block_number: Option<u64>,
+    /// Arbitrum's `gasUsedForL1` or the OP stack's `l1GasUsed`
+    #[serde(
+        default,
+        alias = "gas_used_for_l1",
+        alias = "receipt_gas_used_for_l1",
+        alias = "receipt_l1_gas_used"
+    )]
+    pub l1_gas_used: Option<u64>,
+    #[serde(
+        default,
+        alias = "receipt_l1_fee",
+        deserialize_with = "value_from_string"
+    )]
+    pub l1_fee: Option<U256>,
+    #[serde(
+        default,
+        alias = "receipt_l1_blob_base_fee",
+        deserialize_with = "value_from_string"
+    )]
+    pub l1_blob_base_fee: Option<U256>,
+    /// Versioned hashes of the EIP-4844 blobs carried by the transaction
+    #[serde(default)]
+    pub blob_versioned_hashes: Vec<H256>,
+}